@@ -0,0 +1,172 @@
+//! making the ride easier when the rider struggles and harder when they cruise, from what the equipment reads
+//!
+//! a PI controller turns how far the rider is off their own cadence (and off the watts the ride asks for,
+//! when it asks for any the equipment doesn't hold itself) into a factor on the planned levels
+
+use std::fmt;
+
+/// how far the factor may go, so a bad reading or a long break can't take the ride apart
+const BOUNDS: (f64, f64) = (0.7, 1.2);
+/// how fast the rider's own cadence follows what they ride, in seconds
+const REFERENCE_TIME: f64 = 120.;
+/// how fast the error follows the readings, in seconds, so single readings don't count
+const ERROR_TIME: f64 = 10.;
+/// a share of error that's just riding, not struggling or cruising
+const DEAD_BAND: f64 = 0.05;
+/// how much of the error goes straight into the factor
+const PROPORTIONAL: f64 = 0.3;
+/// how much of the error goes into the factor every second it lasts
+const INTEGRAL: f64 = 0.004;
+/// how much the factor has to move before it gets logged again
+const LOG_STEP: f64 = 0.05;
+/// how long the controller waits for the rider to settle into a cadence before it starts adjusting
+const WARMUP: f64 = 60.;
+
+/// an entry of the session log
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjustment {
+    /// seconds into the ride
+    pub time: f64,
+    pub factor: f64,
+    /// the smoothed error at the time, below 0 for struggling
+    pub error: f64,
+}
+
+/// the factor on the planned levels, and how it got there
+#[derive(Debug, Clone)]
+pub struct Adaptive {
+    time: f64,
+    /// the cadence the rider settles into, nothing until they pedal
+    reference: Option<f64>,
+    error: f64,
+    integral: f64,
+    factor: f64,
+    log: Vec<Adjustment>,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Adaptive {
+            time: 0.,
+            reference: None,
+            error: 0.,
+            integral: 0.,
+            factor: 1.,
+            log: Vec::new(),
+        }
+    }
+}
+
+impl Adaptive {
+    /// take a reading of `cadence` and `watts` that lasted `seconds`
+    ///
+    /// `expected` are the watts the ride asked for, which only say something when the equipment doesn't hold
+    /// them itself. a standing rider isn't struggling, so readings without a cadence are skipped
+    pub fn update(&mut self, cadence: f64, watts: f64, expected: Option<f64>, seconds: f64) {
+        if cadence <= 0. || seconds <= 0. {
+            return;
+        }
+        self.time += seconds;
+        let reference = *self.reference.get_or_insert(cadence);
+        let mut error = (cadence - reference) / reference;
+        if let Some(expected) = expected.filter(|&expected| expected > 0.) {
+            error = (error + (watts - expected) / expected) / 2.;
+        }
+        // the reference follows slowly, so a collapse shows up before it's the new normal
+        self.reference = Some(reference + (cadence - reference) * (seconds / REFERENCE_TIME).min(1.));
+        self.error += (error - self.error) * (seconds / ERROR_TIME).min(1.);
+        if self.time < WARMUP {
+            return;
+        }
+
+        let error = if self.error.abs() < DEAD_BAND {
+            0.
+        } else {
+            self.error - DEAD_BAND.copysign(self.error)
+        };
+        let integral = self.integral + error * seconds;
+        let unclamped = 1. + PROPORTIONAL * error + INTEGRAL * integral;
+        self.factor = unclamped.clamp(BOUNDS.0, BOUNDS.1);
+        // the integral stops growing against the bounds, so it comes back as soon as the rider does
+        if unclamped == self.factor {
+            self.integral = integral;
+        }
+
+        let logged = self.log.last().map_or(1., |adjustment| adjustment.factor);
+        if (self.factor - logged).abs() >= LOG_STEP {
+            self.log.push(Adjustment {
+                time: self.time,
+                factor: self.factor,
+                error: self.error,
+            });
+        }
+    }
+
+    pub fn factor(&self) -> f64 {
+        self.factor
+    }
+
+    /// the planned level with the factor on its share of the range
+    pub fn level(&self, planned: i16, min: i16, max: i16) -> i16 {
+        let share = (planned - min) as f64 * self.factor;
+        (min + share.round() as i16).clamp(min, max)
+    }
+}
+
+impl fmt::Display for Adaptive {
+    /// the session summary, the factor it ended on and every time it moved by `LOG_STEP`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (low, high) = self
+            .log
+            .iter()
+            .fold((1f64, 1f64), |(low, high), adjustment| (low.min(adjustment.factor), high.max(adjustment.factor)));
+        writeln!(f, "ended at ×{:.2}, went from ×{low:.2} to ×{high:.2}", self.factor)?;
+        for adjustment in &self.log {
+            writeln!(
+                f,
+                "{:>3}:{:02} ×{:.2} ({})",
+                (adjustment.time / 60.) as u64,
+                (adjustment.time % 60.) as u64,
+                adjustment.factor,
+                if adjustment.error < 0. { "struggling" } else { "cruising" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_follows_the_rider() {
+        let mut adaptive = Adaptive::default();
+        // settled in at 90 rpm, right at the watts
+        for _ in 0..120 {
+            adaptive.update(90., 200., Some(200.), 1.);
+        }
+        assert_eq!(adaptive.factor(), 1.);
+        assert_eq!(adaptive.level(11, 1, 21), 11);
+
+        // the cadence collapses and the watts with it
+        for _ in 0..60 {
+            adaptive.update(60., 130., Some(200.), 1.);
+        }
+        let struggling = adaptive.factor();
+        assert!(struggling < 0.9, "{struggling}");
+        assert!(adaptive.level(11, 1, 21) < 11);
+        // stopping for a drink doesn't count
+        adaptive.update(0., 0., Some(200.), 30.);
+        assert_eq!(adaptive.factor(), struggling);
+
+        // cruising for long enough takes it up to the bound, and no further
+        for _ in 0..600 {
+            adaptive.update(100., 260., Some(200.), 1.);
+        }
+        assert_eq!(adaptive.factor(), BOUNDS.1);
+        assert_eq!(adaptive.level(21, 1, 21), 21);
+        assert!(adaptive.log.len() >= 2);
+        assert!(adaptive.to_string().starts_with("ended at ×1.20"), "{adaptive}");
+    }
+}
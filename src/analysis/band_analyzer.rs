@@ -0,0 +1,151 @@
+use super::stft::Stft;
+use super::{Analyze, AnalyzerOption, AnalyzerOptions, Feature, Frames};
+use anyhow::Context as _;
+use symphonia::core::audio::SignalSpec;
+
+const BANDS: AnalyzerOption = AnalyzerOption {
+    name: "bands",
+    default: "20-60:1,60-250:0.8,250-500:0.3",
+    help: "frequency bands (in Hz) and their weights, as lo-hi:weight,... (sub, bass and low-mids by default)",
+};
+
+const FLOOR: AnalyzerOption = AnalyzerOption {
+    name: "floor",
+    default: "-50",
+    help: "band energy (in dBFS) that maps to a score of 0",
+};
+
+const CEILING: AnalyzerOption = AnalyzerOption {
+    name: "ceiling",
+    default: "-5",
+    help: "band energy (in dBFS) that maps to a score of 1",
+};
+
+/// size of the fft, long enough to tell 20 Hz from 40 Hz apart
+const FFT_SIZE: usize = 4096;
+
+struct Band {
+    low: f32,
+    high: f32,
+    weight: f64,
+}
+
+fn parse_bands(bands: &str) -> anyhow::Result<Vec<Band>> {
+    let bands = bands
+        .split(',')
+        .map(|text| {
+            let parse = || -> Option<Band> {
+                let (range, weight) = text.trim().split_once(':')?;
+                let (low, high) = range.split_once('-')?;
+                Some(Band {
+                    low: low.trim().parse().ok()?,
+                    high: high.trim().parse().ok()?,
+                    weight: weight.trim().parse().ok()?,
+                })
+            };
+            let band = parse().with_context(|| format!("band `{text}` should look like lo-hi:weight"))?;
+            if band.low >= band.high || band.weight < 0. {
+                anyhow::bail!("band `{text}` needs lo < hi and a weight of at least 0");
+            }
+            Ok(band)
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    if bands.iter().all(|band| band.weight == 0.) {
+        anyhow::bail!("at least one band needs a weight above 0");
+    }
+    Ok(bands)
+}
+
+/// scores the energy in a few weighted frequency bands
+///
+/// by default this follows the kick and the bass, which is what you feel in your legs,
+/// while hi-hats and vocals are left out
+pub struct BandAnalyzer {
+    sample_rate: u32,
+    stft: Stft,
+    bands: Vec<Band>,
+    floor: f64,
+    ceiling: f64,
+    score: f64,
+}
+
+impl Analyze for BandAnalyzer {
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self> {
+        let floor: f64 = options.get(&FLOOR)?;
+        let ceiling: f64 = options.get(&CEILING)?;
+        if ceiling <= floor {
+            anyhow::bail!("band ceiling ({ceiling}) must be above the floor ({floor})");
+        }
+        Ok(BandAnalyzer {
+            sample_rate: spec.rate,
+            stft: Stft::new(FFT_SIZE, FFT_SIZE),
+            bands: parse_bands(&options.get::<String>(&BANDS)?)?,
+            floor,
+            ceiling,
+            score: 0.,
+        })
+    }
+
+    fn options() -> &'static [AnalyzerOption] {
+        &[BANDS, FLOOR, CEILING]
+    }
+
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>> {
+        let spectra = self.stft.push(&frames.mono());
+        if spectra.is_empty() {
+            // not enough samples for a full fft yet, so the previous score still stands
+            return Ok(vec![("score", self.score)]);
+        }
+
+        // a full scale sine comes out of a hann windowed fft at a quarter of the fft size
+        let normalization = 4. / FFT_SIZE as f32;
+        let mut weighted = 0.;
+        let mut total_weight = 0.;
+        for band in &self.bands {
+            let mut power = 0.;
+            for spectrum in &spectra {
+                for (bin, magnitude) in spectrum.iter().enumerate() {
+                    let frequency = self.stft.frequency(bin, self.sample_rate);
+                    if frequency >= band.low && frequency < band.high {
+                        power += ((magnitude * normalization) as f64).powi(2);
+                    }
+                }
+            }
+            let db = 10. * (power / spectra.len() as f64).max(1e-12).log10();
+            weighted += band.weight * ((db - self.floor) / (self.ceiling - self.floor)).clamp(0., 1.);
+            total_weight += band.weight;
+        }
+        self.score = weighted / total_weight;
+        Ok(vec![("score", self.score)])
+    }
+
+    fn reset(&mut self) {
+        self.stft.reset();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::feed;
+    use symphonia::core::audio::Channels;
+
+    fn sine_score(frequency: f32) -> anyhow::Result<f64> {
+        let spec = SignalSpec::new(44_100, Channels::FRONT_LEFT);
+        let mut analyzer = BandAnalyzer::new(spec, &AnalyzerOptions::default())?;
+        let samples: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| 0.5 * (2. * std::f32::consts::PI * frequency * i as f32 / 44_100.).sin())
+            .collect();
+        let features = feed(&mut analyzer, &samples, spec)?;
+        Ok(features[0][0].1)
+    }
+
+    #[test]
+    fn test_bass_outscores_treble() -> anyhow::Result<()> {
+        let bass = sine_score(50.)?;
+        let treble = sine_score(5000.)?;
+        assert!(bass > 0.5, "bass scored {bass}");
+        assert!(treble < 0.1, "treble scored {treble}");
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use super::Frames;
+use super::onset::OnsetDetector;
+use crate::profile::{Tempo, TempoSegment, TempoSource};
+
+/// slowest tempo we look for
+const MIN_BPM: f64 = 60.;
+/// fastest tempo we look for
+const MAX_BPM: f64 = 200.;
+/// tempo estimates get pulled towards this, to pick between e.g. 70 and 140 bpm for the same song
+const PRIOR_BPM: f64 = 120.;
+/// width of the pull towards `PRIOR_BPM`, in octaves
+const PRIOR_WIDTH: f64 = 1.;
+/// length of the windows used for the tempo map, in seconds
+const MAP_WINDOW: f64 = 8.;
+/// distance between the windows used for the tempo map, in seconds
+const MAP_HOP: f64 = 4.;
+/// relative tempo change that starts a new segment in the tempo map
+const MAP_TOLERANCE: f64 = 0.04;
+/// how strictly beats have to follow the tempo, higher means less wiggle room
+const TIGHTNESS: f64 = 100.;
+
+/// estimates tempo and beat positions of a whole track from its onsets
+///
+/// onsets are collected while the track is scanned, and the tempo is estimated once the
+/// whole track has been seen, by looking for the most regular spacing between onsets
+pub struct BeatTracker {
+    rate: f64,
+    detector: OnsetDetector,
+    envelope: Vec<f64>,
+}
+
+impl BeatTracker {
+    pub fn new(sample_rate: u32) -> Self {
+        BeatTracker {
+            rate: OnsetDetector::rate(sample_rate),
+            detector: OnsetDetector::new(sample_rate),
+            envelope: Vec::new(),
+        }
+    }
+
+    /// collect the onsets of a block of frames, returning their average strength
+    pub fn push(&mut self, frames: &Frames<'_>) -> f64 {
+        let strengths = self.detector.push(&frames.mono());
+        self.envelope.extend_from_slice(&strengths);
+        if strengths.is_empty() {
+            return 0.;
+        }
+        strengths.iter().sum::<f64>() / strengths.len() as f64
+    }
+
+    /// like `Analyze::reset`, the onset envelope so far stays
+    pub fn reset(&mut self) {
+        self.detector.reset();
+    }
+
+    /// estimate the tempo of everything pushed so far, if there's any rhythm to be found
+    pub fn finish(&self) -> Option<Tempo> {
+        let envelope = normalize(&self.envelope, self.rate);
+        let (bpm, confidence) = estimate(&envelope, self.rate, PRIOR_BPM, PRIOR_WIDTH)?;
+        let map = tempo_map(&envelope, self.rate, bpm);
+        let beats = track_beats(&envelope, self.rate, &map);
+        Some(Tempo {
+            bpm,
+            source: TempoSource::Detected,
+            confidence,
+            beats,
+            map,
+        })
+    }
+}
+
+/// remove the slowly moving part of the onset envelope, and scale it to unit deviation
+fn normalize(envelope: &[f64], rate: f64) -> Vec<f64> {
+    let radius = (rate * 0.2) as usize;
+    let mut prefix = vec![0.; envelope.len() + 1];
+    for (i, value) in envelope.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + value;
+    }
+    let detrended: Vec<f64> = (0..envelope.len())
+        .map(|i| {
+            let lo = i.saturating_sub(radius);
+            let hi = (i + radius + 1).min(envelope.len());
+            let mean = (prefix[hi] - prefix[lo]) / (hi - lo) as f64;
+            (envelope[i] - mean).max(0.)
+        })
+        .collect();
+    let deviation = (detrended.iter().map(|v| v * v).sum::<f64>() / detrended.len().max(1) as f64).sqrt();
+    if deviation <= f64::EPSILON {
+        return detrended;
+    }
+    detrended.iter().map(|v| v / deviation).collect()
+}
+
+fn lag_for(bpm: f64, rate: f64) -> f64 {
+    60. * rate / bpm
+}
+
+/// the most likely tempo of the envelope, and how sure we are of it (0.0 to 1.0)
+fn estimate(envelope: &[f64], rate: f64, prior_bpm: f64, prior_width: f64) -> Option<(f64, f64)> {
+    let min_lag = lag_for(MAX_BPM, rate).floor().max(1.) as usize;
+    let max_lag = lag_for(MIN_BPM, rate).ceil() as usize;
+    if envelope.len() <= max_lag * 2 {
+        return None;
+    }
+    let mean = envelope.iter().sum::<f64>() / envelope.len() as f64;
+    let centered: Vec<f64> = envelope.iter().map(|v| v - mean).collect();
+    let autocorrelation: Vec<f64> = (0..=max_lag + 1)
+        .map(|lag| {
+            let products = centered.iter().zip(&centered[lag..]).map(|(a, b)| a * b);
+            products.sum::<f64>() / (centered.len() - lag) as f64
+        })
+        .collect();
+    if autocorrelation[0] <= f64::EPSILON {
+        return None;
+    }
+
+    let weighted = |lag: usize| {
+        let bpm = 60. * rate / lag as f64;
+        let octaves = (bpm / prior_bpm).log2() / prior_width;
+        autocorrelation[lag] * (-0.5 * octaves * octaves).exp()
+    };
+    let best = (min_lag..=max_lag).max_by(|&a, &b| weighted(a).total_cmp(&weighted(b)))?;
+    if autocorrelation[best] <= 0. {
+        return None;
+    }
+
+    // the peak is somewhere between lags, so fit a parabola through its neighbours
+    let (left, center, right) = (
+        autocorrelation[best - 1],
+        autocorrelation[best],
+        autocorrelation[best + 1],
+    );
+    let curvature = left - 2. * center + right;
+    let shift = if curvature < 0. {
+        (0.5 * (left - right) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.
+    };
+    let bpm = 60. * rate / (best as f64 + shift);
+    let confidence = (center / autocorrelation[0]).clamp(0., 1.);
+    Some((bpm, confidence))
+}
+
+/// local tempo estimates over the track, merged into segments of steady tempo
+fn tempo_map(envelope: &[f64], rate: f64, bpm: f64) -> Vec<TempoSegment> {
+    let window = (MAP_WINDOW * rate) as usize;
+    let hop = (MAP_HOP * rate) as usize;
+    let mut local = Vec::new();
+    let mut start = 0;
+    while start + window <= envelope.len() {
+        // stay close to the global tempo, so a window doesn't flip to double or half time
+        let estimate = estimate(&envelope[start..start + window], rate, bpm, 0.5);
+        let previous = local.last().copied().unwrap_or(bpm);
+        local.push(match estimate {
+            Some((local_bpm, confidence)) if confidence > 0.1 => local_bpm,
+            _ => previous,
+        });
+        start += hop;
+    }
+
+    // a median over neighbouring windows gets rid of single odd estimates
+    let smoothed: Vec<f64> = (0..local.len())
+        .map(|i| {
+            let mut neighbours = local[i.saturating_sub(1)..(i + 2).min(local.len())].to_vec();
+            neighbours.sort_by(f64::total_cmp);
+            neighbours[neighbours.len() / 2]
+        })
+        .collect();
+
+    let mut map: Vec<TempoSegment> = Vec::new();
+    let mut members = 0.;
+    for (i, &local_bpm) in smoothed.iter().enumerate() {
+        match map.last_mut() {
+            Some(segment) if (local_bpm - segment.bpm).abs() / segment.bpm <= MAP_TOLERANCE => {
+                // keep a running mean of the windows in the segment
+                members += 1.;
+                segment.bpm += (local_bpm - segment.bpm) / members;
+            }
+            _ => {
+                let start = if map.is_empty() {
+                    0.
+                } else {
+                    (i * hop) as f64 / rate + (MAP_WINDOW - MAP_HOP) / 2.
+                };
+                map.push(TempoSegment {
+                    start,
+                    bpm: local_bpm,
+                });
+                members = 1.;
+            }
+        }
+    }
+    if map.is_empty() {
+        map.push(TempoSegment { start: 0., bpm });
+    }
+    map
+}
+
+/// place beats on strong onsets, while keeping them spaced according to the tempo map
+///
+/// this is the dynamic programming approach from Ellis (2007): every onset frame gets the best
+/// score of a beat sequence ending there, and the best sequence is traced back from the end
+fn track_beats(envelope: &[f64], rate: f64, map: &[TempoSegment]) -> Vec<f64> {
+    let period_at = |frame: usize| {
+        let time = frame as f64 / rate;
+        let bpm = map
+            .iter()
+            .take_while(|segment| segment.start <= time)
+            .last()
+            .map_or(map[0].bpm, |segment| segment.bpm);
+        lag_for(bpm, rate)
+    };
+
+    let mut cumulative = vec![0.; envelope.len()];
+    let mut backlink: Vec<Option<usize>> = vec![None; envelope.len()];
+    for i in 0..envelope.len() {
+        let period = period_at(i);
+        let lo = (i as f64 - 2. * period).round().max(0.) as usize;
+        let hi = (i as f64 - period / 2.).round();
+        let mut best: Option<(usize, f64)> = None;
+        if hi >= 0. {
+            let hi = (hi as usize).min(i.saturating_sub(1));
+            for (j, &previous) in cumulative.iter().enumerate().take(hi + 1).skip(lo) {
+                let stretch = ((i - j) as f64 / period).ln();
+                let score = previous - TIGHTNESS * stretch * stretch;
+                if best.is_none_or(|(_, best_score)| score > best_score) {
+                    best = Some((j, score));
+                }
+            }
+        }
+        cumulative[i] = envelope[i] + best.map_or(0., |(_, score)| score.max(0.));
+        backlink[i] = best.filter(|&(_, score)| score > 0.).map(|(j, _)| j);
+    }
+
+    // the last beat is the best scoring frame within the last beat period
+    let Some(last_period) = envelope.len().checked_sub(1).map(period_at) else {
+        return Vec::new();
+    };
+    let tail = envelope.len().saturating_sub(last_period.ceil() as usize);
+    let Some(mut beat) = (tail..envelope.len()).max_by(|&a, &b| cumulative[a].total_cmp(&cumulative[b])) else {
+        return Vec::new();
+    };
+    let mut beats = vec![beat as f64 / rate];
+    while let Some(previous) = backlink[beat] {
+        beats.push(previous as f64 / rate);
+        beat = previous;
+    }
+    beats.reverse();
+    beats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::frames;
+    use symphonia::core::audio::{Channels, SignalSpec};
+
+    #[test]
+    fn test_click_track_tempo() {
+        let sample_rate = 44_100;
+        let spec = SignalSpec::new(sample_rate, Channels::FRONT_LEFT);
+        let bpm = 128.;
+        let beat_length = (60. / bpm * sample_rate as f64) as usize;
+        // 30 seconds of short noise bursts on every beat
+        let samples: Vec<f32> = (0..sample_rate as usize * 30)
+            .map(|i| {
+                if i % beat_length < 400 {
+                    ((i * 7919 % 200) as f32 / 100. - 1.) * 0.8
+                } else {
+                    0.
+                }
+            })
+            .collect();
+
+        let mut tracker = BeatTracker::new(sample_rate);
+        for frames in frames(&samples, spec) {
+            tracker.push(&frames);
+        }
+        let tempo = tracker.finish().unwrap();
+        assert!((tempo.bpm - bpm).abs() < 1.5, "detected {} bpm", tempo.bpm);
+        assert!(tempo.confidence > 0.5, "confidence {}", tempo.confidence);
+        assert_eq!(tempo.map.len(), 1);
+        // roughly one beat per beat length, give or take the edges
+        let expected = 30. * bpm / 60.;
+        assert!((tempo.beats.len() as f64 - expected).abs() < 3., "{} beats", tempo.beats.len());
+    }
+}
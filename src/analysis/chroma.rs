@@ -0,0 +1,20 @@
+/// lowest and highest frequencies that count towards pitch classes
+const RANGE: (f32, f32) = (60., 5000.);
+
+/// fold a magnitude spectrum into the energy of each of the 12 pitch classes, starting at A
+pub fn chroma(spectrum: &[f32], bin_width: f32) -> [f32; 12] {
+    let mut classes = [0.; 12];
+    for (bin, &magnitude) in spectrum.iter().enumerate().skip(1) {
+        let frequency = bin as f32 * bin_width;
+        if frequency < RANGE.0 {
+            continue;
+        }
+        if frequency > RANGE.1 {
+            break;
+        }
+        let semitones = 12. * (frequency / 440.).log2();
+        let class = semitones.round().rem_euclid(12.) as usize % 12;
+        classes[class] += magnitude * magnitude;
+    }
+    classes
+}
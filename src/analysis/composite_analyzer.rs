@@ -0,0 +1,193 @@
+use anyhow::Context as _;
+use symphonia::core::audio::SignalSpec;
+
+use super::normalize::stretch;
+use super::{Analyze, AnalyzerOption, AnalyzerOptions, AnalyzerType, Feature, Frames, get_analyzer};
+use crate::profile::FeatureCurve;
+
+const MIX: AnalyzerOption = AnalyzerOption {
+    name: "mix",
+    default: "lufs:0.6,band:0.3,flux:0.1",
+    help: "analyzers to blend and their weights, as analyzer:weight,... (options of a single analyzer can be set as e.g. band.floor=-40)",
+};
+
+/// percentiles of each score that get stretched to 0.0 and 1.0 before blending
+const NORMALIZE_RANGE: (f64, f64) = (0.05, 0.95);
+
+struct Part {
+    analyzer_type: AnalyzerType,
+    weight: f64,
+    analyzer: Box<dyn Analyze>,
+    scores: Vec<f64>,
+}
+
+fn parse_mix(mix: &str) -> anyhow::Result<Vec<(AnalyzerType, f64)>> {
+    let parts = mix
+        .split(',')
+        .map(|text| {
+            let (name, weight) = text
+                .trim()
+                .split_once(':')
+                .with_context(|| format!("mix entry `{text}` should look like analyzer:weight"))?;
+            let analyzer_type: AnalyzerType = name.trim().parse()?;
+            if analyzer_type == AnalyzerType::Composite {
+                anyhow::bail!("a composite analyzer can't contain another composite analyzer");
+            }
+            let weight: f64 = weight
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid weight in mix entry `{text}`"))?;
+            if weight < 0. {
+                anyhow::bail!("mix entry `{text}` needs a weight of at least 0");
+            }
+            Ok((analyzer_type, weight))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for (i, (analyzer_type, _)) in parts.iter().enumerate() {
+        if parts[..i].iter().any(|(other, _)| other == analyzer_type) {
+            anyhow::bail!("`{analyzer_type}` is in the mix more than once, give it a single weight");
+        }
+    }
+    if parts.iter().map(|(_, weight)| weight).sum::<f64>() <= 0. {
+        anyhow::bail!("the weights in the mix need to add up to more than 0");
+    }
+    Ok(parts)
+}
+
+/// fails if two analyzers in the mix put out a feature of the same name, which would end up mixed into one curve
+fn unique<'a>(names: impl IntoIterator<Item = &'a str>) -> anyhow::Result<()> {
+    let mut seen = Vec::new();
+    for name in names {
+        if seen.contains(&name) {
+            anyhow::bail!("more than one analyzer in the mix puts out `{name}`");
+        }
+        seen.push(name);
+    }
+    Ok(())
+}
+
+/// blends the scores of several analyzers, e.g. 60% loudness, 30% bass and 10% busyness
+///
+/// each score gets normalized over the whole track before blending, so an analyzer that
+/// happens to hover around 0.2 weighs in as much as one that spans the full range
+pub struct CompositeAnalyzer {
+    parts: Vec<Part>,
+}
+
+impl CompositeAnalyzer {
+    fn blend(&self, score: impl Fn(&Part) -> f64) -> f64 {
+        let total_weight: f64 = self.parts.iter().map(|part| part.weight).sum();
+        self.parts.iter().map(|part| part.weight * score(part)).sum::<f64>() / total_weight
+    }
+}
+
+impl Analyze for CompositeAnalyzer {
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self> {
+        let parts = parse_mix(&options.get::<String>(&MIX)?)?
+            .into_iter()
+            .map(|(analyzer_type, weight)| {
+                Ok(Part {
+                    analyzer_type,
+                    weight,
+                    analyzer: get_analyzer(analyzer_type, spec, &options.scoped(analyzer_type.name()))?,
+                    scores: Vec::new(),
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(CompositeAnalyzer { parts })
+    }
+
+    fn options() -> &'static [AnalyzerOption] {
+        &[MIX]
+    }
+
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>> {
+        let mut passed_through = Vec::new();
+        for part in &mut self.parts {
+            let mut score = 0.;
+            for (name, value) in part.analyzer.analyze(frames)? {
+                if name == "score" {
+                    score = value;
+                } else {
+                    passed_through.push((name, value));
+                }
+            }
+            part.scores.push(score);
+        }
+        unique(passed_through.iter().map(|(name, _)| *name))?;
+        // a rough blend while streaming, the normalized blend comes with `finish`
+        let blend = self.blend(|part| part.scores.last().copied().unwrap_or_default());
+        let mut features = vec![("score", blend)];
+        features.extend(passed_through);
+        Ok(features)
+    }
+
+    fn reset(&mut self) {
+        for part in &mut self.parts {
+            part.analyzer.reset();
+        }
+    }
+
+    fn finish(&mut self) -> anyhow::Result<Vec<FeatureCurve>> {
+        let mut curves = Vec::new();
+        for part in &mut self.parts {
+            for curve in part.analyzer.finish()? {
+                if curve.name == "score" {
+                    part.scores = curve.values;
+                } else {
+                    curves.push(curve);
+                }
+            }
+            part.scores = stretch(&part.scores, NORMALIZE_RANGE.0, NORMALIZE_RANGE.1);
+            curves.push(FeatureCurve {
+                name: format!("{}_score", part.analyzer_type),
+                values: part.scores.clone(),
+            });
+        }
+
+        unique(curves.iter().map(|curve| curve.name.as_str()))?;
+
+        let frames = self.parts.iter().map(|part| part.scores.len()).min().unwrap_or(0);
+        let values = (0..frames).map(|i| self.blend(|part| part.scores[i])).collect();
+        curves.push(FeatureCurve {
+            name: String::from("score"),
+            values,
+        });
+        Ok(curves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::feed;
+    use symphonia::core::audio::Channels;
+
+    #[test]
+    fn test_blend_is_normalized() -> anyhow::Result<()> {
+        let spec = SignalSpec::new(44_100, Channels::FRONT_LEFT);
+        let options = AnalyzerOptions::parse(&[
+            String::from("mix=lufs:0.5,band:0.5"),
+            String::from("band.floor=-60"),
+        ])?;
+        let mut analyzer = CompositeAnalyzer::new(spec, &options)?;
+        // a bass tone that fades in, so both analyzers have something to follow
+        let samples: Vec<f32> = (0..44_100 * 4)
+            .map(|i| {
+                let t = i as f64 / 44_100.;
+                (t / 4. * (2. * std::f64::consts::PI * 55. * t).sin()) as f32
+            })
+            .collect();
+        feed(&mut analyzer, &samples, spec)?;
+        let curves = analyzer.finish()?;
+        let score = curves.iter().find(|curve| curve.name == "score").unwrap();
+        assert!(curves.iter().any(|curve| curve.name == "band_score"));
+        assert!(score.values.iter().all(|value| (0. ..=1.).contains(value)));
+        assert_eq!(score.values.first(), Some(&0.));
+        assert_eq!(score.values.last(), Some(&1.));
+        assert!(parse_mix("composite:1").is_err());
+        assert!(parse_mix("lufs:0.5,band:0.2,lufs:0.3").is_err());
+        assert!(unique(["lufs", "flux", "lufs"]).is_err());
+        Ok(())
+    }
+}
@@ -1,38 +1,60 @@
 use std::ops::Div;
 
-use super::Analyze;
+use super::{Analyze, AnalyzerOption, AnalyzerOptions, Feature, Frames};
 use spectrum_analyzer::scaling::divide_by_N_sqrt;
 use spectrum_analyzer::{FrequencyLimit, samples_fft_to_spectrum};
+use symphonia::core::audio::SignalSpec;
+
+const MAX_FREQUENCY: AnalyzerOption = AnalyzerOption {
+    name: "max_frequency",
+    default: "2000",
+    help: "highest frequency (in Hz) included in the average",
+};
 
 pub struct FftAnalyzer {
     sample_rate: u32,
-    scale: f64,
+    max_frequency: f32,
 }
 
 impl Analyze for FftAnalyzer {
-    fn new(sample_rate: u32, _channels: u32, scale: f64) -> anyhow::Result<Self> {
-        Ok(FftAnalyzer { sample_rate, scale })
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self> {
+        Ok(FftAnalyzer {
+            sample_rate: spec.rate,
+            max_frequency: options.get(&MAX_FREQUENCY)?,
+        })
+    }
+
+    fn options() -> &'static [AnalyzerOption] {
+        &[MAX_FREQUENCY]
     }
 
-    fn freq_score(&mut self, samplebuffer: Vec<f32>) -> anyhow::Result<f64> {
-        if !samplebuffer.len().is_power_of_two() {
-            return Ok(0.);
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>> {
+        if frames.len() < 2 {
+            return Ok(vec![("score", 0.)]);
         }
+        // the fft wants a power of two, so drop whatever doesn't fit
+        let mut samples = frames.mono();
+        let len = if samples.len().is_power_of_two() {
+            samples.len()
+        } else {
+            samples.len().next_power_of_two() / 2
+        };
+        samples.truncate(len);
         let spectrum_hann_window = samples_fft_to_spectrum(
             // (windowed) samples
-            &samplebuffer,
+            &samples,
             // sampling rate
             self.sample_rate,
             // optional frequency limit: e.g. only interested in frequencies 50 <= f <= 150?
-            FrequencyLimit::Max(2000.),
+            FrequencyLimit::Max(self.max_frequency),
             // optional scale
             Some(&divide_by_N_sqrt),
         )?;
-        Ok(spectrum_hann_window
+        let score = spectrum_hann_window
             .data()
             .iter()
             .fold(0., |acc, &(_, val)| acc + val.val() as f64)
-            .div(spectrum_hann_window.data().len() as f64)
-            * self.scale)
+            .div(spectrum_hann_window.data().len() as f64);
+        Ok(vec![("score", score)])
     }
 }
@@ -0,0 +1,175 @@
+use std::collections::VecDeque;
+
+use super::onset::OnsetDetector;
+use super::{Analyze, AnalyzerOption, AnalyzerOptions, Feature, Frames};
+use symphonia::core::audio::SignalSpec;
+
+const WINDOW: AnalyzerOption = AnalyzerOption {
+    name: "window",
+    default: "2",
+    help: "seconds of onsets counted towards the onset density",
+};
+
+const MAX_DENSITY: AnalyzerOption = AnalyzerOption {
+    name: "max_density",
+    default: "8",
+    help: "onsets per second that map to a score of 1",
+};
+
+const THRESHOLD: AnalyzerOption = AnalyzerOption {
+    name: "threshold",
+    default: "1.5",
+    help: "how far above the recent average flux a peak has to be to count as an onset",
+};
+
+/// flux below this is never an onset, so the noise floor of quiet passages doesn't count
+const MIN_FLUX: f64 = 0.05;
+/// seconds of flux averaged to decide whether a peak stands out
+const AVERAGE_WINDOW: f64 = 0.5;
+
+/// scores how busy the music is, by counting onsets (hits, plucks, note changes) per second
+///
+/// dense, driving sections score high even when they're not louder than the rest of the track,
+/// while sustained pads and drones score low no matter how loud they are
+pub struct FluxAnalyzer {
+    detector: OnsetDetector,
+    rate: f64,
+    window: f64,
+    max_density: f64,
+    threshold: f64,
+    recent: VecDeque<f64>,
+    onsets: VecDeque<usize>,
+    hop: usize,
+    previous: (f64, f64),
+}
+
+impl Analyze for FluxAnalyzer {
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self> {
+        let window: f64 = options.get(&WINDOW)?;
+        let max_density: f64 = options.get(&MAX_DENSITY)?;
+        if window <= 0. || max_density <= 0. {
+            anyhow::bail!("flux window and max_density must be above 0");
+        }
+        Ok(FluxAnalyzer {
+            detector: OnsetDetector::new(spec.rate),
+            rate: OnsetDetector::rate(spec.rate),
+            window,
+            max_density,
+            threshold: options.get(&THRESHOLD)?,
+            recent: VecDeque::new(),
+            onsets: VecDeque::new(),
+            hop: 0,
+            previous: (0., 0.),
+        })
+    }
+
+    fn options() -> &'static [AnalyzerOption] {
+        &[WINDOW, MAX_DENSITY, THRESHOLD]
+    }
+
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>> {
+        let strengths = self.detector.push(&frames.mono());
+        let average_len = (AVERAGE_WINDOW * self.rate) as usize;
+        for &strength in &strengths {
+            // the previous value is a peak if it's higher than both of its neighbours
+            let (before, candidate) = self.previous;
+            let average = self.recent.iter().sum::<f64>() / self.recent.len().max(1) as f64;
+            if candidate > before
+                && candidate >= strength
+                && candidate > MIN_FLUX
+                && candidate > average * self.threshold
+            {
+                self.onsets.push_back(self.hop.saturating_sub(1));
+            }
+            self.recent.push_back(candidate);
+            if self.recent.len() > average_len {
+                self.recent.pop_front();
+            }
+            self.previous = (candidate, strength);
+            self.hop += 1;
+        }
+
+        let window_hops = (self.window * self.rate) as usize;
+        while self
+            .onsets
+            .front()
+            .is_some_and(|&onset| onset + window_hops < self.hop)
+        {
+            self.onsets.pop_front();
+        }
+
+        let density = self.onsets.len() as f64 / self.window;
+        let flux = strengths.iter().sum::<f64>() / strengths.len().max(1) as f64;
+        Ok(vec![
+            ("score", (density / self.max_density).clamp(0., 1.)),
+            ("flux", flux),
+            ("density", density),
+        ])
+    }
+
+    fn reset(&mut self) {
+        // the onsets found so far are on the timeline, and still count towards the density
+        self.detector.reset();
+        self.recent.clear();
+        self.previous = (0., 0.);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::feed;
+    use symphonia::core::audio::Channels;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    /// feed `seconds` of a signal through the analyzer, and return the last score
+    fn last_score(seconds: usize, signal: impl Fn(usize) -> f32) -> anyhow::Result<f64> {
+        let spec = SignalSpec::new(SAMPLE_RATE, Channels::FRONT_LEFT);
+        let mut analyzer = FluxAnalyzer::new(spec, &AnalyzerOptions::default())?;
+        let samples: Vec<f32> = (0..SAMPLE_RATE as usize * seconds).map(signal).collect();
+        let features = feed(&mut analyzer, &samples, spec)?;
+        Ok(features.last().map_or(0., |features| features[0].1))
+    }
+
+    fn click(i: usize, per_second: usize) -> f32 {
+        if i % (SAMPLE_RATE as usize / per_second) < 200 {
+            ((i * 7919 % 200) as f32 / 100. - 1.) * 0.8
+        } else {
+            0.
+        }
+    }
+
+    fn tone(i: usize) -> f32 {
+        (0.8 * (2. * std::f64::consts::PI * 220. * i as f64 / SAMPLE_RATE as f64).sin()) as f32
+    }
+
+    #[test]
+    fn test_click_track_is_busy() -> anyhow::Result<()> {
+        let score = last_score(6, |i| click(i, 4))?;
+        assert!((score - 0.5).abs() < 0.15, "4 clicks per second scored {score}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_denser_clicks_score_higher() -> anyhow::Result<()> {
+        let sparse = last_score(6, |i| click(i, 2))?;
+        let dense = last_score(6, |i| click(i, 6))?;
+        assert!(dense > sparse, "dense {dense} <= sparse {sparse}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sustained_tone_is_calm() -> anyhow::Result<()> {
+        let score = last_score(6, tone)?;
+        assert!(score < 0.05, "a sustained tone scored {score}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_clicks_over_a_tone_are_busy() -> anyhow::Result<()> {
+        let score = last_score(6, |i| tone(i) * 0.5 + click(i, 4))?;
+        assert!(score > 0.3, "clicks over a tone scored {score}");
+        Ok(())
+    }
+}
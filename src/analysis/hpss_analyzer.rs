@@ -0,0 +1,225 @@
+use super::stft::Stft;
+use super::{Analyze, AnalyzerOption, AnalyzerOptions, Feature, Frames};
+use crate::profile::FeatureCurve;
+use symphonia::core::audio::SignalSpec;
+
+const MIX: AnalyzerOption = AnalyzerOption {
+    name: "mix",
+    default: "1",
+    help: "share of the percussive part in the score, the rest comes from the harmonic part",
+};
+
+const FLOOR: AnalyzerOption = AnalyzerOption {
+    name: "floor",
+    default: "-50",
+    help: "energy (in dBFS) that maps to a score of 0",
+};
+
+const CEILING: AnalyzerOption = AnalyzerOption {
+    name: "ceiling",
+    default: "-5",
+    help: "energy (in dBFS) that maps to a score of 1",
+};
+
+const FFT_SIZE: usize = 2048;
+const HOP: usize = 1024;
+/// length of the median filters, in stft frames along time and in bins along frequency
+const KERNEL: usize = 17;
+/// only bins below this are separated, there's little of either part above it
+const MAX_FREQUENCY: f32 = 10_000.;
+
+/// splits the track into its harmonic and percussive parts, and scores their energy
+///
+/// sustained sounds are smooth along time and spiky along frequency, while hits are the other
+/// way around. median filters in both directions pull the two apart (Fitzgerald, 2010), so the
+/// drums can drive the ride even when a loud synth pad sits on top of them.
+///
+/// the filters need the whole track, so the curves only come out of `finish`
+pub struct HpssAnalyzer {
+    stft: Stft,
+    max_bin: usize,
+    mix: f64,
+    floor: f64,
+    ceiling: f64,
+    spectra: Vec<Vec<f32>>,
+    /// how many stft frames each analyzed block ended up with
+    block_frames: Vec<usize>,
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    let middle = values.len() / 2;
+    *values.select_nth_unstable_by(middle, f32::total_cmp).1
+}
+
+impl HpssAnalyzer {
+    /// the harmonic and percussive energy of every stft frame
+    fn separate(&self) -> (Vec<f64>, Vec<f64>) {
+        let frames = self.spectra.len();
+        let half = KERNEL / 2;
+        let mut window = Vec::with_capacity(KERNEL);
+        let mut harmonic = vec![0.; frames];
+        let mut percussive = vec![0.; frames];
+        // a full scale sine comes out of a hann windowed fft at a quarter of the fft size
+        let normalization = 4. / FFT_SIZE as f32;
+        for frame in 0..frames {
+            for bin in 0..self.max_bin {
+                window.clear();
+                window.extend(
+                    self.spectra[frame.saturating_sub(half)..(frame + half + 1).min(frames)]
+                        .iter()
+                        .map(|spectrum| spectrum[bin]),
+                );
+                let smooth_in_time = median(&mut window);
+
+                window.clear();
+                window.extend_from_slice(
+                    &self.spectra[frame][bin.saturating_sub(half)..(bin + half + 1).min(self.max_bin)],
+                );
+                let smooth_in_frequency = median(&mut window);
+
+                // soft masks share every bin out between the two parts
+                let (h, p) = (smooth_in_time.powi(2), smooth_in_frequency.powi(2));
+                let magnitude = self.spectra[frame][bin] * normalization;
+                if h + p > 0. {
+                    harmonic[frame] += (magnitude * h / (h + p)).powi(2) as f64;
+                    percussive[frame] += (magnitude * p / (h + p)).powi(2) as f64;
+                }
+            }
+        }
+        (harmonic, percussive)
+    }
+
+    /// average stft frame energies over the blocks they came from, one value (in dBFS) per block
+    fn per_block(&self, values: &[f64]) -> Vec<f64> {
+        let mut start = 0;
+        let mut previous = values.first().copied().unwrap_or_default();
+        self.block_frames
+            .iter()
+            .map(|&count| {
+                if count > 0 {
+                    previous = values[start..start + count].iter().sum::<f64>() / count as f64;
+                }
+                start += count;
+                10. * previous.max(1e-12).log10()
+            })
+            .collect()
+    }
+
+    fn score(&self, db: f64) -> f64 {
+        ((db - self.floor) / (self.ceiling - self.floor)).clamp(0., 1.)
+    }
+}
+
+impl Analyze for HpssAnalyzer {
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self> {
+        let mix: f64 = options.get(&MIX)?;
+        let floor: f64 = options.get(&FLOOR)?;
+        let ceiling: f64 = options.get(&CEILING)?;
+        if !(0. ..=1.).contains(&mix) {
+            anyhow::bail!("hpss mix ({mix}) must be between 0 and 1");
+        }
+        if ceiling <= floor {
+            anyhow::bail!("hpss ceiling ({ceiling}) must be above the floor ({floor})");
+        }
+        let stft = Stft::new(FFT_SIZE, HOP);
+        let max_bin = (0..stft.bins())
+            .take_while(|&bin| stft.frequency(bin, spec.rate) <= MAX_FREQUENCY)
+            .count();
+        Ok(HpssAnalyzer {
+            stft,
+            max_bin,
+            mix,
+            floor,
+            ceiling,
+            spectra: Vec::new(),
+            block_frames: Vec::new(),
+        })
+    }
+
+    fn options() -> &'static [AnalyzerOption] {
+        &[MIX, FLOOR, CEILING]
+    }
+
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>> {
+        let spectra = self.stft.push(&frames.mono());
+        self.block_frames.push(spectra.len());
+        self.spectra
+            .extend(spectra.into_iter().map(|mut spectrum| {
+                spectrum.truncate(self.max_bin);
+                spectrum
+            }));
+        // nothing to go on until the whole track is in
+        Ok(vec![("score", 0.)])
+    }
+
+    fn reset(&mut self) {
+        self.stft.reset();
+    }
+
+    fn finish(&mut self) -> anyhow::Result<Vec<FeatureCurve>> {
+        let (harmonic, percussive) = self.separate();
+        let harmonic = self.per_block(&harmonic);
+        let percussive = self.per_block(&percussive);
+        let score = harmonic
+            .iter()
+            .zip(&percussive)
+            .map(|(&h, &p)| self.mix * self.score(p) + (1. - self.mix) * self.score(h))
+            .collect();
+        Ok(vec![
+            FeatureCurve {
+                name: String::from("score"),
+                values: score,
+            },
+            FeatureCurve {
+                name: String::from("harmonic"),
+                values: harmonic,
+            },
+            FeatureCurve {
+                name: String::from("percussive"),
+                values: percussive,
+            },
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::feed;
+    use symphonia::core::audio::Channels;
+
+    fn curves(signal: impl Fn(usize) -> f32) -> anyhow::Result<Vec<FeatureCurve>> {
+        let spec = SignalSpec::new(44_100, Channels::FRONT_LEFT);
+        let mut analyzer = HpssAnalyzer::new(spec, &AnalyzerOptions::default())?;
+        let samples: Vec<f32> = (0..44_100 * 3).map(signal).collect();
+        feed(&mut analyzer, &samples, spec)?;
+        analyzer.finish()
+    }
+
+    fn mean(curves: &[FeatureCurve], name: &str) -> f64 {
+        let curve = curves.iter().find(|curve| curve.name == name).unwrap();
+        curve.values.iter().sum::<f64>() / curve.values.len() as f64
+    }
+
+    #[test]
+    fn test_tone_is_harmonic_and_clicks_are_percussive() -> anyhow::Result<()> {
+        let tone = curves(|i| (0.5 * (2. * std::f64::consts::PI * 330. * i as f64 / 44_100.).sin()) as f32)?;
+        let clicks = curves(|i| if i % 11_025 < 100 { 0.8 } else { 0. })?;
+        assert!(mean(&tone, "harmonic") > mean(&tone, "percussive") + 10.);
+        assert!(mean(&clicks, "percussive") > mean(&clicks, "harmonic") + 3.);
+        assert!(mean(&clicks, "score") > mean(&tone, "score"));
+
+        // a chained stream breaks the signal off, but the curves still cover both streams
+        let spec = SignalSpec::new(44_100, Channels::FRONT_LEFT);
+        let mut analyzer = HpssAnalyzer::new(spec, &AnalyzerOptions::default())?;
+        let samples: Vec<f32> = (0..44_100 * 3).map(|i| if i % 11_025 < 100 { 0.8 } else { 0. }).collect();
+        let (first, second) = samples.split_at(4096 * 16);
+        let blocks = feed(&mut analyzer, first, spec)?.len();
+        analyzer.reset();
+        let blocks = blocks + feed(&mut analyzer, second, spec)?.len();
+        let chained = analyzer.finish()?;
+        let score = chained.iter().find(|curve| curve.name == "score").unwrap();
+        assert_eq!(score.values.len(), blocks);
+        Ok(())
+    }
+}
@@ -1,27 +1,55 @@
-use super::Analyze;
+use super::{Analyze, AnalyzerOption, AnalyzerOptions, Feature, Frames};
 use ebur128::{EbuR128, Mode};
+use symphonia::core::audio::SignalSpec;
+
+const FLOOR: AnalyzerOption = AnalyzerOption {
+    name: "floor",
+    default: "-40",
+    help: "momentary loudness (in LUFS) that maps to a score of 0",
+};
+
+const CEILING: AnalyzerOption = AnalyzerOption {
+    name: "ceiling",
+    default: "-3",
+    help: "momentary loudness (in LUFS) that maps to a score of 1",
+};
+
+/// the absolute gate of EBU R128, anything quieter is reported as this
+const SILENCE: f64 = -70.;
 
 pub struct LufsAnalyzer {
     ebur128: EbuR128,
-    scale: f64,
+    floor: f64,
+    ceiling: f64,
 }
 
 impl Analyze for LufsAnalyzer {
-    fn new(sample_rate: u32, channels: u32, scale: f64) -> anyhow::Result<Self> {
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self> {
+        let floor: f64 = options.get(&FLOOR)?;
+        let ceiling: f64 = options.get(&CEILING)?;
+        if ceiling <= floor {
+            anyhow::bail!("lufs ceiling ({ceiling}) must be above the floor ({floor})");
+        }
         Ok(LufsAnalyzer {
-            ebur128: EbuR128::new(channels, sample_rate, Mode::M)?,
-            scale,
+            ebur128: EbuR128::new(spec.channels.count() as u32, spec.rate, Mode::M)?,
+            floor,
+            ceiling,
         })
     }
 
-    fn freq_score(&mut self, samplebuffer: Vec<f32>) -> anyhow::Result<f64> {
-        if !samplebuffer.len().is_power_of_two() {
-            return Ok(0.);
-        }
-        self.ebur128.add_frames_f32(&samplebuffer)?;
-        let lufs = self.ebur128.loudness_momentary()?;
-        let scaled_lufs = ((lufs + 40.) / 37.).max(0.).min(1.) * self.scale;
-        Ok(scaled_lufs)
+    fn options() -> &'static [AnalyzerOption] {
+        &[FLOOR, CEILING]
+    }
+
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>> {
+        self.ebur128.add_frames_f32(frames.samples)?;
+        let lufs = self.ebur128.loudness_momentary()?.max(SILENCE);
+        let scaled_lufs = ((lufs - self.floor) / (self.ceiling - self.floor)).clamp(0., 1.);
+        Ok(vec![("score", scaled_lufs), ("lufs", lufs)])
+    }
+
+    fn reset(&mut self) {
+        self.ebur128.reset();
     }
 }
 
@@ -29,14 +57,19 @@ impl Analyze for LufsAnalyzer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use symphonia::core::audio::Channels;
 
     #[test]
-    fn test_freq_score() -> anyhow::Result<()> {
-        let mut analyzer = LufsAnalyzer::new(44_100, 2, 1.0)?;
+    fn test_analyze() -> anyhow::Result<()> {
+        let spec = SignalSpec::new(44_100, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut analyzer = LufsAnalyzer::new(spec, &AnalyzerOptions::default())?;
         let samplebuffer: Vec<f32> = vec![f32::MAX; 4096];
-        let score = analyzer.freq_score(samplebuffer)?;
-        println!("score: {score}");
-        assert!(score >= 0.);
+        let features = analyzer.analyze(&Frames {
+            samples: &samplebuffer,
+            spec,
+            timestamp: 0.,
+        })?;
+        assert!(features.iter().any(|&(name, score)| name == "score" && score >= 0.));
         Ok(())
     }
-}
\ No newline at end of file
+}
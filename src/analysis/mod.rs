@@ -1,26 +1,269 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context as _;
+use symphonia::core::audio::SignalSpec;
+
+use crate::profile::FeatureCurve;
+
+mod band_analyzer;
+mod beat_tracker;
+mod chroma;
+mod composite_analyzer;
 mod fft_analyzer;
+mod flux_analyzer;
+mod hpss_analyzer;
 mod lufs_analyzer;
+mod normalize;
+mod onset;
+mod segmentation;
+mod spectral_analyzer;
+mod stft;
+
+pub use beat_tracker::BeatTracker;
+pub use normalize::{percentile, stretch};
+pub use segmentation::{Segmenter, plateau};
+pub use spectral_analyzer::key_name;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AnalyzerType {
     Fft,
     Lufs,
+    Band,
+    Flux,
+    Hpss,
+    Composite,
+    Spectral,
+}
+
+impl AnalyzerType {
+    pub const ALL: &[AnalyzerType] = &[
+        AnalyzerType::Fft,
+        AnalyzerType::Lufs,
+        AnalyzerType::Band,
+        AnalyzerType::Flux,
+        AnalyzerType::Hpss,
+        AnalyzerType::Composite,
+        AnalyzerType::Spectral,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnalyzerType::Fft => "fft",
+            AnalyzerType::Lufs => "lufs",
+            AnalyzerType::Band => "band",
+            AnalyzerType::Flux => "flux",
+            AnalyzerType::Hpss => "hpss",
+            AnalyzerType::Composite => "composite",
+            AnalyzerType::Spectral => "spectral",
+        }
+    }
+
+    /// the options the analyzer understands, with their defaults
+    pub fn options(&self) -> &'static [AnalyzerOption] {
+        match self {
+            AnalyzerType::Fft => fft_analyzer::FftAnalyzer::options(),
+            AnalyzerType::Lufs => lufs_analyzer::LufsAnalyzer::options(),
+            AnalyzerType::Band => band_analyzer::BandAnalyzer::options(),
+            AnalyzerType::Flux => flux_analyzer::FluxAnalyzer::options(),
+            AnalyzerType::Hpss => hpss_analyzer::HpssAnalyzer::options(),
+            AnalyzerType::Composite => composite_analyzer::CompositeAnalyzer::options(),
+            AnalyzerType::Spectral => spectral_analyzer::SpectralAnalyzer::options(),
+        }
+    }
+}
+
+impl FromStr for AnalyzerType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        AnalyzerType::ALL
+            .iter()
+            .find(|analyzer| analyzer.name() == s)
+            .copied()
+            .with_context(|| {
+                let known: Vec<_> = AnalyzerType::ALL.iter().map(|a| a.name()).collect();
+                format!("unknown analyzer `{s}`, expected one of: {}", known.join(", "))
+            })
+    }
+}
+
+impl fmt::Display for AnalyzerType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// a block of decoded, interleaved frames, borrowed from the decoder
+pub struct Frames<'a> {
+    pub samples: &'a [f32],
+    pub spec: SignalSpec,
+    /// seconds into the track of the first frame in the block, for analyzers that place what they find in time
+    pub timestamp: f64,
+}
+
+impl Frames<'_> {
+    pub fn channels(&self) -> usize {
+        self.spec.channels.count()
+    }
+
+    /// number of frames (samples per channel) in the block
+    pub fn len(&self) -> usize {
+        self.samples.len() / self.channels().max(1)
+    }
+
+    /// the samples of a single channel
+    pub fn channel(&self, channel: usize) -> impl Iterator<Item = f32> + '_ {
+        self.samples
+            .iter()
+            .skip(channel)
+            .step_by(self.channels().max(1))
+            .copied()
+    }
+
+    /// all channels averaged into one
+    pub fn mono(&self) -> Vec<f32> {
+        let channels = self.channels().max(1);
+        let mut mono = vec![0.; self.len()];
+        for channel in 0..channels {
+            for (sum, sample) in mono.iter_mut().zip(self.channel(channel)) {
+                *sum += sample / channels as f32;
+            }
+        }
+        mono
+    }
+}
+
+/// a named value computed for a block of frames
+///
+/// every analyzer emits a `score` feature from 0.0 to 1.0, which the level curve is derived from
+pub type Feature = (&'static str, f64);
+
+/// an option an analyzer understands, e.g. `--analyzer-option max_frequency=1000`
+pub struct AnalyzerOption {
+    pub name: &'static str,
+    pub default: &'static str,
+    pub help: &'static str,
+}
+
+/// `key=value` options given to the analyzer, falling back to the defaults the analyzer advertises
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct AnalyzerOptions(BTreeMap<String, String>);
+
+impl AnalyzerOptions {
+    pub fn parse(pairs: &[String]) -> anyhow::Result<Self> {
+        let mut options = BTreeMap::new();
+        for pair in pairs {
+            let (key, value) = pair
+                .split_once('=')
+                .with_context(|| format!("analyzer option `{pair}` should look like key=value"))?;
+            options.insert(key.trim().to_string(), value.trim().to_string());
+        }
+        Ok(AnalyzerOptions(options))
+    }
+
+    /// the options for one analyzer among several, where `name.key=value` wins over `key=value`
+    pub fn scoped(&self, name: &str) -> Self {
+        let mut options: BTreeMap<String, String> = self
+            .0
+            .iter()
+            .filter(|(key, _)| !key.contains('.'))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        let prefix = format!("{name}.");
+        for (key, value) in &self.0 {
+            if let Some(key) = key.strip_prefix(&prefix) {
+                options.insert(key.to_string(), value.clone());
+            }
+        }
+        AnalyzerOptions(options)
+    }
+
+    pub fn get<T>(&self, option: &AnalyzerOption) -> anyhow::Result<T>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self
+            .0
+            .get(option.name)
+            .map(String::as_str)
+            .unwrap_or(option.default);
+        value
+            .parse()
+            .map_err(|e| anyhow::anyhow!("invalid value `{value}` for analyzer option `{}`: {e}", option.name))
+    }
 }
 
 pub trait Analyze {
-    fn new(sample_rate: u32, channels: u32, scale: f64) -> anyhow::Result<Self>
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self>
+    where
+        Self: Sized;
+    /// the options this analyzer understands
+    fn options() -> &'static [AnalyzerOption]
     where
         Self: Sized;
-    fn freq_score(&mut self, samplebuffer: Vec<f32>) -> anyhow::Result<f64>;
+    /// analyze the next block of frames
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>>;
+    /// the signal breaks off here (e.g. a chained stream starts), so forget the audio buffered so far
+    ///
+    /// the timeline carries on: whatever was collected for `finish` stays
+    fn reset(&mut self) {}
+    /// called once the whole track has been analyzed
+    ///
+    /// analyzers that need to see the whole track can return complete curves here,
+    /// which replace the streamed feature with the same name
+    fn finish(&mut self) -> anyhow::Result<Vec<FeatureCurve>> {
+        Ok(Vec::new())
+    }
 }
 
 pub fn get_analyzer(
     analyzer_type: AnalyzerType,
-    sample_rate: u32,
-    channels: u32,
-    scale: f64,
+    spec: SignalSpec,
+    options: &AnalyzerOptions,
 ) -> anyhow::Result<Box<dyn Analyze>> {
     match analyzer_type {
-        AnalyzerType::Fft => Ok(Box::new(fft_analyzer::FftAnalyzer::new(sample_rate, channels, scale)?)),
-        AnalyzerType::Lufs => Ok(Box::new(lufs_analyzer::LufsAnalyzer::new(sample_rate, channels, scale)?))
+        AnalyzerType::Fft => Ok(Box::new(fft_analyzer::FftAnalyzer::new(spec, options)?)),
+        AnalyzerType::Lufs => Ok(Box::new(lufs_analyzer::LufsAnalyzer::new(spec, options)?)),
+        AnalyzerType::Band => Ok(Box::new(band_analyzer::BandAnalyzer::new(spec, options)?)),
+        AnalyzerType::Flux => Ok(Box::new(flux_analyzer::FluxAnalyzer::new(spec, options)?)),
+        AnalyzerType::Hpss => Ok(Box::new(hpss_analyzer::HpssAnalyzer::new(spec, options)?)),
+        AnalyzerType::Composite => Ok(Box::new(composite_analyzer::CompositeAnalyzer::new(
+            spec, options,
+        )?)),
+        AnalyzerType::Spectral => Ok(Box::new(spectral_analyzer::SpectralAnalyzer::new(spec, options)?)),
+    }
+}
+
+/// every analyzer along with the options it understands, for `--help`
+pub fn describe() -> String {
+    let mut description = String::from("Analyzer option as key=value, can be given multiple times\n");
+    for analyzer in AnalyzerType::ALL {
+        description.push_str(&format!("\n{analyzer}:\n"));
+        for option in analyzer.options() {
+            description.push_str(&format!(
+                "    {}={} :: {}\n",
+                option.name, option.default, option.help
+            ));
+        }
     }
+    description
+}
+
+/// `samples` in blocks of 4096, timestamped from the start, for tests that stream a signal
+#[cfg(test)]
+pub fn frames(samples: &[f32], spec: SignalSpec) -> impl Iterator<Item = Frames<'_>> {
+    samples.chunks(4096).enumerate().map(move |(i, block)| Frames {
+        samples: block,
+        spec,
+        timestamp: (i * 4096) as f64 / spec.rate as f64,
+    })
+}
+
+/// run every block of `samples` through `analyzer`, and collect the features of each
+#[cfg(test)]
+pub fn feed(analyzer: &mut impl Analyze, samples: &[f32], spec: SignalSpec) -> anyhow::Result<Vec<Vec<Feature>>> {
+    frames(samples, spec).map(|frames| analyzer.analyze(&frames)).collect()
 }
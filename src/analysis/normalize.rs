@@ -0,0 +1,26 @@
+/// the value that a fraction `p` (from 0.0 to 1.0) of the values falls below
+pub fn percentile(values: &[f64], p: f64) -> f64 {
+    if values.is_empty() {
+        return 0.;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = p.clamp(0., 1.) * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+/// rescale values so that the `low` to `high` percentile range covers 0.0 to 1.0
+///
+/// percentiles instead of the minimum and maximum keep a single spike or a silent intro
+/// from squashing everything else
+pub fn stretch(values: &[f64], low: f64, high: f64) -> Vec<f64> {
+    let (floor, ceiling) = (percentile(values, low), percentile(values, high));
+    if ceiling - floor <= f64::EPSILON {
+        return values.iter().map(|value| value.clamp(0., 1.)).collect();
+    }
+    values
+        .iter()
+        .map(|value| ((value - floor) / (ceiling - floor)).clamp(0., 1.))
+        .collect()
+}
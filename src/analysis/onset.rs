@@ -0,0 +1,56 @@
+use super::stft::Stft;
+
+/// spectral flux onset detection
+///
+/// compares every spectrum to the previous one, and sums up how much energy appeared in each bin.
+/// percussive hits light up many bins at once, while sustained notes barely register
+pub struct OnsetDetector {
+    stft: Stft,
+    previous: Vec<f32>,
+    /// highest bin taken into account
+    max_bin: usize,
+}
+
+impl OnsetDetector {
+    pub const SIZE: usize = 2048;
+    pub const HOP: usize = 512;
+
+    pub fn new(sample_rate: u32) -> Self {
+        let stft = Stft::new(Self::SIZE, Self::HOP);
+        // most of the rhythm lives below 8 kHz, anything above is mostly hiss and cymbal wash
+        let max_bin = (0..stft.bins())
+            .take_while(|&bin| stft.frequency(bin, sample_rate) <= 8000.)
+            .count();
+        OnsetDetector {
+            previous: vec![0.; stft.bins()],
+            stft,
+            max_bin,
+        }
+    }
+
+    /// onset strength values per second
+    pub fn rate(sample_rate: u32) -> f64 {
+        sample_rate as f64 / Self::HOP as f64
+    }
+
+    /// push mono samples, and get the onset strength of every hop completed by them
+    pub fn push(&mut self, samples: &[f32]) -> Vec<f64> {
+        let mut strengths = Vec::new();
+        for spectrum in self.stft.push(samples) {
+            let mut flux = 0.;
+            for (bin, &magnitude) in spectrum.iter().enumerate().take(self.max_bin) {
+                // log compression keeps quiet passages from disappearing next to loud ones
+                let compressed = (1. + 100. * magnitude).ln();
+                flux += (compressed - self.previous[bin]).max(0.) as f64;
+                self.previous[bin] = compressed;
+            }
+            strengths.push(flux / self.max_bin as f64);
+        }
+        strengths
+    }
+
+    pub fn reset(&mut self) {
+        self.stft.reset();
+        self.previous.iter_mut().for_each(|value| *value = 0.);
+    }
+}
@@ -0,0 +1,296 @@
+use super::Frames;
+use super::chroma::chroma;
+use super::stft::Stft;
+use crate::profile::{Section, SectionLabel};
+
+const FFT_SIZE: usize = 4096;
+/// feature vectors per second the self-similarity is computed on
+const RATE: f64 = 2.;
+/// half width of the novelty kernel, in seconds. a section change needs this much context on both sides
+const KERNEL_SECONDS: f64 = 8.;
+/// sections shorter than this get merged into their neighbours
+const MIN_SECTION: f64 = 8.;
+/// how many standard deviations above the average novelty a peak needs to be a boundary
+const THRESHOLD: f64 = 0.5;
+/// number of log spaced bands describing the timbre
+const TIMBRE_BANDS: usize = 16;
+
+/// finds the sections of a song (intro, verse, chorus, ...) by looking for moments where the
+/// music stops resembling what came before
+///
+/// every couple hundred milliseconds gets a feature vector (harmony and timbre), every vector is
+/// compared to the ones around it, and a checkerboard kernel slid along the diagonal of that
+/// self-similarity matrix lights up where one block of similar music meets another (Foote, 2000)
+pub struct Segmenter {
+    sample_rate: u32,
+    stft: Stft,
+    features: Vec<Vec<f32>>,
+    /// seconds covered by the frames pushed so far
+    duration: f64,
+}
+
+impl Segmenter {
+    pub fn new(sample_rate: u32) -> Self {
+        Segmenter {
+            sample_rate,
+            stft: Stft::new(FFT_SIZE, FFT_SIZE),
+            features: Vec::new(),
+            duration: 0.,
+        }
+    }
+
+    pub fn push(&mut self, frames: &Frames<'_>) {
+        self.duration = frames.timestamp + frames.len() as f64 / self.sample_rate as f64;
+        let bin_width = self.stft.frequency(1, self.sample_rate);
+        for spectrum in self.stft.push(&frames.mono()) {
+            let mut feature: Vec<f32> = chroma(&spectrum, bin_width).to_vec();
+            let total: f32 = feature.iter().sum();
+            if total > 0. {
+                feature.iter_mut().for_each(|class| *class /= total);
+            }
+            // bands from ~40 Hz up, each twice as wide as the one before
+            for band in 0..TIMBRE_BANDS {
+                let low = 40. * 2f32.powf(band as f32 * 9. / TIMBRE_BANDS as f32);
+                let high = low * 2f32.powf(9. / TIMBRE_BANDS as f32);
+                let energy: f32 = spectrum
+                    .iter()
+                    .enumerate()
+                    .filter(|&(bin, _)| (low..high).contains(&(bin as f32 * bin_width)))
+                    .map(|(_, magnitude)| magnitude * magnitude)
+                    .sum();
+                feature.push((1. + energy).ln());
+            }
+            self.features.push(feature);
+        }
+    }
+
+    /// like `Analyze::reset`, the features found so far stay
+    pub fn reset(&mut self) {
+        self.stft.reset();
+    }
+
+    /// the sections of everything pushed so far, each with the average of `levels` within it
+    pub fn finish(&self, levels: &[f64], frame_rate: f64) -> Vec<Section> {
+        if self.duration <= 0. {
+            return Vec::new();
+        }
+        let (features, seconds_per_feature) = self.downsample();
+        let boundaries = boundaries(&novelty(&features));
+
+        let mut edges = vec![0.];
+        edges.extend(boundaries.iter().map(|&boundary| boundary as f64 * seconds_per_feature));
+        edges.push(self.duration);
+
+        let mut sections: Vec<Section> = edges
+            .windows(2)
+            .map(|edge| {
+                let (start, end) = (edge[0], edge[1]);
+                let first = (start * frame_rate) as usize;
+                let last = ((end * frame_rate) as usize).min(levels.len());
+                let level = if first < last {
+                    levels[first..last].iter().sum::<f64>() / (last - first) as f64
+                } else {
+                    0.
+                };
+                Section {
+                    start,
+                    end,
+                    label: SectionLabel::Verse,
+                    level,
+                }
+            })
+            .collect();
+        label(&mut sections);
+        sections
+    }
+
+    /// average the feature vectors down to about `RATE` per second, and give every dimension unit variance
+    ///
+    /// returns the vectors, and the seconds each of them covers
+    fn downsample(&self) -> (Vec<Vec<f32>>, f64) {
+        let stft_rate = self.sample_rate as f64 / FFT_SIZE as f64;
+        let group = ((stft_rate / RATE).round() as usize).max(1);
+        let seconds_per_feature = group as f64 / stft_rate;
+        let mut features: Vec<Vec<f32>> = self
+            .features
+            .chunks(group)
+            .map(|chunk| {
+                let mut mean = vec![0.; chunk[0].len()];
+                for feature in chunk {
+                    mean.iter_mut().zip(feature).for_each(|(m, f)| *m += f / chunk.len() as f32);
+                }
+                mean
+            })
+            .collect();
+        let Some(dimensions) = features.first().map(Vec::len) else {
+            return (features, seconds_per_feature);
+        };
+        for dimension in 0..dimensions {
+            let n = features.len() as f32;
+            let mean = features.iter().map(|f| f[dimension]).sum::<f32>() / n;
+            let deviation = (features.iter().map(|f| (f[dimension] - mean).powi(2)).sum::<f32>() / n).sqrt();
+            for feature in &mut features {
+                feature[dimension] = if deviation > 0. {
+                    (feature[dimension] - mean) / deviation
+                } else {
+                    0.
+                };
+            }
+        }
+        (features, seconds_per_feature)
+    }
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f64 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0. { (dot / norm) as f64 } else { 0. }
+}
+
+/// how much the music changes at every point, from a checkerboard kernel along the self-similarity diagonal
+fn novelty(features: &[Vec<f32>]) -> Vec<f64> {
+    let n = features.len();
+    let half = (KERNEL_SECONDS * RATE) as isize;
+    // the kernel never reaches further than its width from the diagonal, so only that band of the
+    // matrix is kept: `band[x][d]` is the similarity of `x` and `x + d`
+    let width = 2 * half as usize;
+    let band: Vec<Vec<f64>> = (0..n)
+        .map(|x| (0..width.min(n - x)).map(|d| cosine(&features[x], &features[x + d])).collect())
+        .collect();
+    (0..n as isize)
+        .map(|i| {
+            let mut sum = 0.;
+            for a in -half..half {
+                for b in -half..half {
+                    let (x, y) = (i + a, i + b);
+                    if x < 0 || y < 0 || x >= n as isize || y >= n as isize {
+                        continue;
+                    }
+                    // same side of the diagonal counts for, across the diagonal counts against
+                    let sign = if (a < 0) == (b < 0) { 1. } else { -1. };
+                    let taper = (-((a as f64 + 0.5).powi(2) + (b as f64 + 0.5).powi(2))
+                        / (2. * (half as f64 / 2.).powi(2)))
+                    .exp();
+                    let (low, high) = (x.min(y) as usize, x.max(y) as usize);
+                    sum += sign * taper * band[low][high - low];
+                }
+            }
+            sum
+        })
+        .collect()
+}
+
+/// peaks of the novelty curve that stand out, at least `MIN_SECTION` apart and away from the edges
+fn boundaries(novelty: &[f64]) -> Vec<usize> {
+    let n = novelty.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let mean = novelty.iter().sum::<f64>() / n as f64;
+    let deviation = (novelty.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+    let threshold = mean + THRESHOLD * deviation;
+    let gap = (MIN_SECTION * RATE) as usize;
+
+    let mut candidates: Vec<usize> = (gap..n.saturating_sub(gap))
+        .filter(|&i| {
+            let lo = i.saturating_sub(gap);
+            let hi = (i + gap + 1).min(n);
+            novelty[i] > threshold && novelty[lo..hi].iter().all(|&v| v <= novelty[i])
+        })
+        .collect();
+    candidates.dedup_by(|a, b| a.abs_diff(*b) < gap);
+    candidates
+}
+
+/// name sections by how they sit relative to the rest of the track
+///
+/// this is a rough guess: quiet sections at the edges are intros and outros, the most intense
+/// sections are choruses, or drops when they come right after a much calmer section
+fn label(sections: &mut [Section]) {
+    if sections.is_empty() {
+        return;
+    }
+    let mut sorted: Vec<f64> = sections.iter().map(|section| section.level).collect();
+    sorted.sort_by(f64::total_cmp);
+    let mean = sorted.iter().sum::<f64>() / sorted.len() as f64;
+    let high = sorted[(sorted.len() * 3 / 4).min(sorted.len() - 1)];
+
+    let last = sections.len() - 1;
+    for i in 0..sections.len() {
+        let level = sections[i].level;
+        let previous = i.checked_sub(1).map(|p| sections[p].level);
+        sections[i].label = if i == 0 && level < mean && last > 0 {
+            SectionLabel::Intro
+        } else if i == last && level < mean && last > 0 {
+            SectionLabel::Outro
+        } else if level >= high && previous.is_some_and(|previous| level - previous > 0.25) {
+            SectionLabel::Drop
+        } else if level >= high && level > mean {
+            SectionLabel::Chorus
+        } else {
+            SectionLabel::Verse
+        };
+    }
+}
+
+/// flatten `levels` to the level of the section they're in
+pub fn plateau(levels: &[f64], sections: &[Section], frame_rate: f64) -> Vec<f64> {
+    levels
+        .iter()
+        .enumerate()
+        .map(|(frame, &level)| {
+            let time = frame as f64 / frame_rate;
+            sections
+                .iter()
+                .find(|section| time >= section.start && time < section.end)
+                .map_or(level, |section| section.level)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::frames;
+    use symphonia::core::audio::{Channels, SignalSpec};
+
+    #[test]
+    fn test_sections_of_three_parts() {
+        let sample_rate = 44_100;
+        let spec = SignalSpec::new(sample_rate, Channels::FRONT_LEFT);
+        let part = sample_rate as usize * 20;
+        // a quiet tone, a loud noisy beat, and a different quiet tone
+        let samples: Vec<f32> = (0..part * 3)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let sample = match i / part {
+                    0 => 0.2 * (2. * std::f64::consts::PI * 220. * t).sin(),
+                    1 if i % 11_025 < 2000 => ((i * 7919 % 200) as f64 / 100. - 1.) * 0.8,
+                    1 => 0.,
+                    _ => 0.2 * (2. * std::f64::consts::PI * 311. * t).sin(),
+                };
+                sample as f32
+            })
+            .collect();
+
+        let mut segmenter = Segmenter::new(sample_rate);
+        for frames in frames(&samples, spec) {
+            segmenter.push(&frames);
+        }
+        let frame_rate = sample_rate as f64 / 4096.;
+        let levels: Vec<f64> = (0..(60. * frame_rate) as usize)
+            .map(|frame| if (20. ..40.).contains(&(frame as f64 / frame_rate)) { 0.9 } else { 0.2 })
+            .collect();
+        let sections = segmenter.finish(&levels, frame_rate);
+
+        assert_eq!(sections.len(), 3, "{sections:?}");
+        assert!((sections[1].start - 20.).abs() < 2., "{sections:?}");
+        assert!((sections[2].start - 40.).abs() < 2., "{sections:?}");
+        assert_eq!(sections[0].label, SectionLabel::Intro);
+        assert_eq!(sections[1].label, SectionLabel::Drop);
+        assert_eq!(sections[2].label, SectionLabel::Outro);
+
+        let flat = plateau(&levels, &sections, frame_rate);
+        assert!(flat[(30. * frame_rate) as usize] > 0.8);
+    }
+}
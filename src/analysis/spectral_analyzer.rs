@@ -0,0 +1,349 @@
+use symphonia::core::audio::SignalSpec;
+
+use super::chroma::chroma;
+use super::stft::Stft;
+use super::{Analyze, AnalyzerOption, AnalyzerOptions, Feature, Frames};
+use crate::profile::FeatureCurve;
+
+const FLOOR: AnalyzerOption = AnalyzerOption {
+    name: "floor",
+    default: "300",
+    help: "spectral centroid (in Hz) that maps to a score of 0",
+};
+
+const CEILING: AnalyzerOption = AnalyzerOption {
+    name: "ceiling",
+    default: "5000",
+    help: "spectral centroid (in Hz) that maps to a score of 1",
+};
+
+const ROLLOFF: AnalyzerOption = AnalyzerOption {
+    name: "rolloff",
+    default: "0.85",
+    help: "share of the energy below the rolloff frequency",
+};
+
+const BUMP: AnalyzerOption = AnalyzerOption {
+    name: "bump",
+    default: "0.2",
+    help: "how much the score goes up after a key change or a jump in brightness, fading out over a few seconds",
+};
+
+const FFT_SIZE: usize = 4096;
+/// seconds of chroma that go into every key estimate
+const KEY_SECONDS: f64 = 8.;
+/// a key has to last this long to count as a modulation, rather than a passing chord
+const MIN_KEY_SECONDS: f64 = 6.;
+/// seconds of brightness compared on either side of a possible jump
+const JUMP_SECONDS: f64 = 2.;
+/// how far the brightness has to move to count as a jump, in octaves
+const JUMP_OCTAVES: f64 = 0.5;
+/// how long a bump takes to fade to about a third, in seconds
+const BUMP_SECONDS: f64 = 4.;
+
+/// Krumhansl-Kessler key profiles, starting at the tonic
+const MAJOR: [f64; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR: [f64; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+const PITCH_CLASSES: [&str; 12] = ["A", "A#", "B", "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#"];
+
+/// the name of a key from the `key` feature: 0 to 11 are A to G# major, 12 to 23 are A to G# minor
+pub fn key_name(key: f64) -> Option<String> {
+    if !(0. ..24.).contains(&key) {
+        return None;
+    }
+    let key = key as usize;
+    let mode = if key < 12 { "major" } else { "minor" };
+    Some(format!("{} {mode}", PITCH_CLASSES[key % 12]))
+}
+
+/// scores how bright the music sounds, by the spectral centroid (the "center of mass" of the
+/// spectrum), and estimates the key, so modulations and sudden brightness can bump the level
+///
+/// emits `centroid` and `rolloff` in Hz while streaming, and the `key` (see [`key_name`],
+/// -1 where unknown), `key_change` and `brightness_jump` (in octaves) curves once the whole
+/// track is known
+pub struct SpectralAnalyzer {
+    sample_rate: u32,
+    stft: Stft,
+    floor: f64,
+    ceiling: f64,
+    rolloff: f64,
+    bump: f64,
+    /// per analyzed block, log2 of the centroid (`None` when silent) and the chroma
+    centroids: Vec<Option<f64>>,
+    chroma: Vec<[f32; 12]>,
+}
+
+impl SpectralAnalyzer {
+    fn rate(&self) -> f64 {
+        self.sample_rate as f64 / FFT_SIZE as f64
+    }
+
+    fn score(&self, centroid: Option<f64>) -> f64 {
+        centroid.map_or(0., |centroid| {
+            ((centroid - self.floor.log2()) / (self.ceiling / self.floor).log2()).clamp(0., 1.)
+        })
+    }
+}
+
+impl Analyze for SpectralAnalyzer {
+    fn new(spec: SignalSpec, options: &AnalyzerOptions) -> anyhow::Result<Self> {
+        let floor: f64 = options.get(&FLOOR)?;
+        let ceiling: f64 = options.get(&CEILING)?;
+        if floor <= 0. || ceiling <= floor {
+            anyhow::bail!("spectral ceiling ({ceiling}) must be above the floor ({floor}), which must be above 0");
+        }
+        let rolloff: f64 = options.get(&ROLLOFF)?;
+        if !(0. ..=1.).contains(&rolloff) {
+            anyhow::bail!("spectral rolloff ({rolloff}) must be between 0 and 1");
+        }
+        Ok(SpectralAnalyzer {
+            sample_rate: spec.rate,
+            stft: Stft::new(FFT_SIZE, FFT_SIZE),
+            floor,
+            ceiling,
+            rolloff,
+            bump: options.get(&BUMP)?,
+            centroids: Vec::new(),
+            chroma: Vec::new(),
+        })
+    }
+
+    fn options() -> &'static [AnalyzerOption] {
+        &[FLOOR, CEILING, ROLLOFF, BUMP]
+    }
+
+    fn analyze(&mut self, frames: &Frames<'_>) -> anyhow::Result<Vec<Feature>> {
+        let bin_width = self.stft.frequency(1, self.sample_rate);
+        let mut features = (None, 0., [0.; 12]);
+        for spectrum in self.stft.push(&frames.mono()) {
+            let energy: Vec<f64> = spectrum.iter().map(|&m| (m * m) as f64).collect();
+            let total: f64 = energy.iter().sum();
+            if total <= 1e-9 {
+                features = (None, 0., [0.; 12]);
+                continue;
+            }
+            let centroid = energy
+                .iter()
+                .enumerate()
+                .map(|(bin, energy)| bin as f64 * bin_width as f64 * energy)
+                .sum::<f64>()
+                / total;
+            let mut cumulative = 0.;
+            let rolloff_bin = energy
+                .iter()
+                .position(|energy| {
+                    cumulative += energy;
+                    cumulative >= total * self.rolloff
+                })
+                .unwrap_or(energy.len());
+            features = (
+                Some(centroid.max(1.).log2()),
+                rolloff_bin as f64 * bin_width as f64,
+                chroma(&spectrum, bin_width),
+            );
+        }
+        let (centroid, rolloff, chroma) = features;
+        self.centroids.push(centroid);
+        self.chroma.push(chroma);
+        Ok(vec![
+            ("score", self.score(centroid)),
+            ("centroid", centroid.map_or(0., f64::exp2)),
+            ("rolloff", rolloff),
+        ])
+    }
+
+    fn reset(&mut self) {
+        self.stft.reset();
+    }
+
+    fn finish(&mut self) -> anyhow::Result<Vec<FeatureCurve>> {
+        let rate = self.rate();
+        let keys = hold_keys(
+            &estimate_keys(&self.chroma, (KEY_SECONDS * rate / 2.) as usize),
+            (MIN_KEY_SECONDS * rate) as usize,
+        );
+        let key_change: Vec<f64> = keys
+            .iter()
+            .enumerate()
+            .map(|(i, &key)| (i > 0 && keys[i - 1] >= 0. && key != keys[i - 1]) as u8 as f64)
+            .collect();
+        let brightness_jump = jumps(&self.centroids, (JUMP_SECONDS * rate) as usize);
+
+        // every modulation and every jump up in brightness kicks off a bump that fades out
+        let mut bump = 0.;
+        let score = (0..keys.len())
+            .map(|i| {
+                bump *= (-1. / (BUMP_SECONDS * rate)).exp();
+                if key_change[i] > 0. || brightness_jump[i] > 0. {
+                    bump = self.bump;
+                }
+                (self.score(self.centroids[i]) + bump).clamp(0., 1.)
+            })
+            .collect();
+
+        let curve = |name: &str, values: Vec<f64>| FeatureCurve {
+            name: name.to_string(),
+            values,
+        };
+        Ok(vec![
+            curve("score", score),
+            curve("key", keys),
+            curve("key_change", key_change),
+            curve("brightness_jump", brightness_jump),
+        ])
+    }
+}
+
+/// the best matching key around every block, by correlating the chroma within `radius` blocks with
+/// every major and minor key profile
+fn estimate_keys(chroma: &[[f32; 12]], radius: usize) -> Vec<f64> {
+    let mut previous = -1.;
+    (0..chroma.len())
+        .map(|i| {
+            let mut window = [0.; 12];
+            for block in &chroma[i.saturating_sub(radius)..(i + radius + 1).min(chroma.len())] {
+                let total: f32 = block.iter().sum();
+                if total > 0. {
+                    window.iter_mut().zip(block).for_each(|(sum, class)| *sum += (class / total) as f64);
+                }
+            }
+            if window.iter().sum::<f64>() <= 0. {
+                return previous;
+            }
+            let best = (0..24)
+                .map(|key| {
+                    let profile = if key < 12 { &MAJOR } else { &MINOR };
+                    let template: Vec<f64> = (0..12).map(|class| profile[(class + 24 - key) % 12]).collect();
+                    (key, correlation(&window, &template))
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map_or(-1., |(key, _)| key as f64);
+            previous = best;
+            best
+        })
+        .collect()
+}
+
+fn correlation(a: &[f64], b: &[f64]) -> f64 {
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (mut covariance, mut variance_a, mut variance_b) = (0., 0., 0.);
+    for (a, b) in a.iter().zip(b) {
+        covariance += (a - mean_a) * (b - mean_b);
+        variance_a += (a - mean_a).powi(2);
+        variance_b += (b - mean_b).powi(2);
+    }
+    covariance / (variance_a * variance_b).sqrt().max(f64::EPSILON)
+}
+
+/// fold keys that last less than `min_length` blocks into the key before them
+fn hold_keys(keys: &[f64], min_length: usize) -> Vec<f64> {
+    let mut runs: Vec<(f64, usize)> = Vec::new();
+    for &key in keys {
+        match runs.last_mut() {
+            Some((last, length)) if *last == key => *length += 1,
+            _ => runs.push((key, 1)),
+        }
+    }
+    let mut held: Vec<(f64, usize)> = Vec::new();
+    for (key, length) in runs {
+        match held.last_mut() {
+            Some((last, last_length)) if *last == key || length < min_length => *last_length += length,
+            _ => held.push((key, length)),
+        }
+    }
+    held.into_iter()
+        .flat_map(|(key, length)| std::iter::repeat_n(key, length))
+        .collect()
+}
+
+/// how far the brightness moves (in octaves) at its sharpest changes, comparing `radius` blocks on
+/// either side, 0 everywhere else
+fn jumps(centroids: &[Option<f64>], radius: usize) -> Vec<f64> {
+    let mean = |range: &[Option<f64>]| {
+        let audible: Vec<f64> = range.iter().flatten().copied().collect();
+        (!audible.is_empty()).then(|| audible.iter().sum::<f64>() / audible.len() as f64)
+    };
+    let change: Vec<f64> = (0..centroids.len())
+        .map(|i| {
+            let before = mean(&centroids[i.saturating_sub(radius)..i]);
+            let after = mean(&centroids[i..(i + radius).min(centroids.len())]);
+            match (before, after) {
+                (Some(before), Some(after)) => after - before,
+                _ => 0.,
+            }
+        })
+        .collect();
+    (0..change.len())
+        .map(|i| {
+            let neighbourhood = &change[i.saturating_sub(radius)..(i + radius + 1).min(change.len())];
+            let peak = neighbourhood.iter().all(|other| other.abs() <= change[i].abs());
+            if peak && change[i].abs() >= JUMP_OCTAVES { change[i] } else { 0. }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analysis::feed;
+    use symphonia::core::audio::Channels;
+
+    #[test]
+    fn test_modulation_and_brightness() -> anyhow::Result<()> {
+        let sample_rate = 44_100;
+        let spec = SignalSpec::new(sample_rate, Channels::FRONT_LEFT);
+        let part = sample_rate as usize * 16;
+        // an A major chord, then a C major chord two octaves up
+        let chord = |frequencies: &[f64], t: f64| {
+            frequencies
+                .iter()
+                .map(|frequency| (2. * std::f64::consts::PI * frequency * t).sin())
+                .sum::<f64>()
+                / frequencies.len() as f64
+        };
+        let samples: Vec<f32> = (0..part * 2)
+            .map(|i| {
+                let t = i as f64 / sample_rate as f64;
+                let chord = if i < part {
+                    chord(&[220., 277.18, 329.63], t)
+                } else {
+                    chord(&[1046.5, 1318.51, 1567.98], t)
+                };
+                (chord * 0.5) as f32
+            })
+            .collect();
+
+        let mut analyzer = SpectralAnalyzer::new(spec, &AnalyzerOptions::default())?;
+        let scores: Vec<f64> = feed(&mut analyzer, &samples, spec)?
+            .iter()
+            .map(|features| features[0].1)
+            .collect();
+        let curves = analyzer.finish()?;
+        let curve = |name: &str| &curves.iter().find(|curve| curve.name == name).unwrap().values;
+
+        let boundary = part as f64 / FFT_SIZE as f64;
+        let near_boundary = |values: &[f64]| {
+            values
+                .iter()
+                .enumerate()
+                .filter(|&(_, &value)| value != 0.)
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+        let key_changes = near_boundary(curve("key_change"));
+        assert_eq!(key_changes.len(), 1, "key changes at {key_changes:?}");
+        assert!((key_changes[0] as f64 - boundary).abs() < 8.);
+        let jumps = near_boundary(curve("brightness_jump"));
+        assert_eq!(jumps.len(), 1, "brightness jumps at {jumps:?}");
+        assert!((jumps[0] as f64 - boundary).abs() < 2.);
+        assert!(curve("brightness_jump")[jumps[0]] > 1.);
+
+        let keys = curve("key");
+        assert_eq!(key_name(keys[10]).as_deref(), Some("A major"));
+        assert_eq!(key_name(*keys.last().unwrap()).as_deref(), Some("C major"));
+        assert!(scores[scores.len() - 10] > scores[10]);
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+
+/// a streaming short-time fourier transform over mono samples
+///
+/// samples can be pushed in blocks of any size, and every `hop` samples a new magnitude
+/// spectrum of the last `size` samples (hann windowed) comes out
+pub struct Stft {
+    size: usize,
+    hop: usize,
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    buffer: Vec<f32>,
+    scratch: Vec<Complex<f32>>,
+}
+
+impl Stft {
+    pub fn new(size: usize, hop: usize) -> Self {
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2. * std::f32::consts::PI * i as f32 / size as f32).cos())
+            .collect();
+        Stft {
+            size,
+            hop,
+            fft: FftPlanner::new().plan_fft_forward(size),
+            window,
+            // start with a window of silence, so the first spectrum is centered on the first hop
+            buffer: vec![0.; size - hop],
+            scratch: vec![Complex::default(); size],
+        }
+    }
+
+    /// number of magnitude bins in every spectrum
+    pub fn bins(&self) -> usize {
+        self.size / 2 + 1
+    }
+
+    /// the frequency (in Hz) of a bin
+    pub fn frequency(&self, bin: usize, sample_rate: u32) -> f32 {
+        bin as f32 * sample_rate as f32 / self.size as f32
+    }
+
+    /// push more samples, and get the magnitude spectra of every hop completed by them
+    pub fn push(&mut self, samples: &[f32]) -> Vec<Vec<f32>> {
+        self.buffer.extend_from_slice(samples);
+        let mut spectra = Vec::new();
+        let mut start = 0;
+        while self.buffer.len() - start >= self.size {
+            for (i, value) in self.scratch.iter_mut().enumerate() {
+                *value = Complex::new(self.buffer[start + i] * self.window[i], 0.);
+            }
+            self.fft.process(&mut self.scratch);
+            spectra.push(self.scratch[..self.bins()].iter().map(|c| c.norm()).collect());
+            start += self.hop;
+        }
+        self.buffer.drain(..start);
+        spectra
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.buffer.resize(self.size - self.hop, 0.);
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::{Arc, RwLock};
+
+use crate::profile::RideProfile;
+
+/// where the audio thread currently is
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Playhead {
+    /// index of the track in the album
+    pub track: usize,
+    /// seconds into the track
+    pub position: f64,
+}
+
+#[derive(Default)]
+struct State {
+    playhead: Option<Playhead>,
+    profile: Option<Arc<RideProfile>>,
+}
+
+/// shared between the audio thread (which moves the playhead) and the equipment loop (which samples it)
+///
+/// the audio thread publishes where it is, and the equipment loop can look up the level at any point
+/// of the current track at its own pace, instead of having to drain a message per packet
+#[derive(Clone, Default)]
+pub struct PlaybackClock {
+    state: Arc<RwLock<State>>,
+}
+
+impl PlaybackClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// publish the profile of a new track, and rewind the playhead to its start
+    pub fn load(&self, track: usize, profile: RideProfile) {
+        let mut state = self.state.write().unwrap();
+        state.playhead = Some(Playhead {
+            track,
+            position: 0.,
+        });
+        state.profile = Some(Arc::new(profile));
+    }
+
+    /// swap the profile of the current track, e.g. after its ride chart changed, without moving the playhead
+    pub fn update(&self, profile: RideProfile) {
+        self.state.write().unwrap().profile = Some(Arc::new(profile));
+    }
+
+    pub fn advance(&self, position: f64) {
+        if let Some(playhead) = self.state.write().unwrap().playhead.as_mut() {
+            playhead.position = position;
+        }
+    }
+
+    /// the current profile, and the playhead moved `lead` seconds ahead
+    pub fn lookahead(&self, lead: f64) -> Option<(Arc<RideProfile>, Playhead)> {
+        let state = self.state.read().unwrap();
+        let playhead = state.playhead?;
+        Some((
+            state.profile.clone()?,
+            Playhead {
+                position: playhead.position + lead,
+                ..playhead
+            },
+        ))
+    }
+}
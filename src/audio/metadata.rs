@@ -0,0 +1,210 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use symphonia::core::meta::{MetadataRevision, StandardTagKey, Tag, Value};
+
+use crate::tags;
+
+/// how loud a track was mastered, from its ReplayGain tags
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ReplayGain {
+    /// in dB
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    /// in dB
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
+}
+
+/// which ReplayGain to play tracks back with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GainMode {
+    /// play tracks as they are
+    Off,
+    /// bring every track to the same loudness
+    Track,
+    /// keep the loudness differences between tracks of an album
+    Album,
+}
+
+impl ReplayGain {
+    /// the factor to multiply samples with, held back so the peak doesn't clip
+    ///
+    /// album gain falls back to track gain, and the other way around
+    pub fn factor(&self, mode: GainMode) -> f64 {
+        let (gain, peak) = match mode {
+            GainMode::Off => return 1.,
+            GainMode::Track => (self.track_gain.or(self.album_gain), self.track_peak.or(self.album_peak)),
+            GainMode::Album => (self.album_gain.or(self.track_gain), self.album_peak.or(self.track_peak)),
+        };
+        let factor = 10f64.powf(gain.unwrap_or(0.) / 20.);
+        match peak {
+            Some(peak) if peak > 0. => factor.min(1. / peak),
+            _ => factor,
+        }
+    }
+}
+
+/// the embedded cover art, without the image itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cover {
+    pub media_type: String,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// size of the image in bytes
+    pub size: usize,
+}
+
+/// what the tags of a track say about it, whatever the format they came in
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackMetadata {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// from a vorbis `BPM` comment, an id3 TBPM frame or an mp4 tmpo atom
+    pub bpm: Option<f64>,
+    pub replay_gain: ReplayGain,
+    pub cover: Option<Cover>,
+    /// the difficulty written by `music-rider tag`, from 1 to 10
+    pub difficulty: Option<f64>,
+    /// the average level written by `music-rider tag`, from 0.0 to 1.0
+    pub average_level: Option<f64>,
+}
+
+impl TrackMetadata {
+    /// gather the metadata from every revision found, later ones win
+    pub fn from_revisions<'a>(revisions: impl IntoIterator<Item = &'a MetadataRevision>) -> Self {
+        let mut metadata = TrackMetadata::default();
+        for revision in revisions {
+            for tag in revision.tags() {
+                metadata.add(tag);
+            }
+            if let Some(visual) = revision.visuals().first() {
+                metadata.cover = Some(Cover {
+                    media_type: visual.media_type.clone(),
+                    width: visual.dimensions.map(|size| size.width),
+                    height: visual.dimensions.map(|size| size.height),
+                    size: visual.data.len(),
+                });
+            }
+        }
+        metadata
+    }
+
+    fn add(&mut self, tag: &Tag) {
+        let text = || match &tag.value {
+            Value::String(value) => Some(value.trim().to_string()).filter(|value| !value.is_empty()),
+            _ => None,
+        };
+        match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => self.title = text().or(self.title.take()),
+            Some(StandardTagKey::Artist) => self.artist = text().or(self.artist.take()),
+            Some(StandardTagKey::Album) => self.album = text().or(self.album.take()),
+            Some(StandardTagKey::Bpm) => self.bpm = bpm(&tag.value).or(self.bpm),
+            Some(StandardTagKey::ReplayGainTrackGain) => {
+                self.replay_gain.track_gain = number(&tag.value).or(self.replay_gain.track_gain)
+            }
+            Some(StandardTagKey::ReplayGainTrackPeak) => {
+                self.replay_gain.track_peak = number(&tag.value).or(self.replay_gain.track_peak)
+            }
+            Some(StandardTagKey::ReplayGainAlbumGain) => {
+                self.replay_gain.album_gain = number(&tag.value).or(self.replay_gain.album_gain)
+            }
+            Some(StandardTagKey::ReplayGainAlbumPeak) => {
+                self.replay_gain.album_peak = number(&tag.value).or(self.replay_gain.album_peak)
+            }
+            _ => {
+                // id3 user defined frames come through as `TXXX:KEY`
+                let key = tag.key.rsplit(':').next().unwrap_or(&tag.key);
+                if ["BPM", "TBPM", "TMPO"].iter().any(|name| key.eq_ignore_ascii_case(name)) {
+                    self.bpm = bpm(&tag.value).or(self.bpm);
+                } else if key.eq_ignore_ascii_case(tags::DIFFICULTY) {
+                    self.difficulty = number(&tag.value).or(self.difficulty);
+                } else if key.eq_ignore_ascii_case(tags::AVERAGE_LEVEL) {
+                    self.average_level = number(&tag.value).or(self.average_level);
+                }
+            }
+        }
+    }
+}
+
+/// a number at the start of a tag, like "128", "127.5" or "-6.20 dB"
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::String(value) => {
+            let value = value.trim();
+            let end = value
+                .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+')))
+                .unwrap_or(value.len());
+            value[..end].parse().ok()
+        }
+        Value::UnsignedInt(value) => Some(*value as f64),
+        Value::SignedInt(value) => Some(*value as f64),
+        Value::Float(value) => Some(*value),
+        _ => None,
+    }
+}
+
+/// a tempo that makes sense, "0" or a garbled tag means there isn't one
+fn bpm(value: &Value) -> Option<f64> {
+    number(value).filter(|bpm| (20. ..=400.).contains(bpm))
+}
+
+impl fmt::Display for TrackMetadata {
+    /// "artist - title (album)", with whatever of it is known
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.artist, &self.title) {
+            (Some(artist), Some(title)) => write!(f, "{artist} - {title}")?,
+            (None, Some(title)) => write!(f, "{title}")?,
+            (Some(artist), None) => write!(f, "{artist}")?,
+            (None, None) => write!(f, "unknown track")?,
+        }
+        if let Some(album) = &self.album {
+            write!(f, " ({album})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use symphonia::core::meta::MetadataBuilder;
+
+    #[test]
+    fn test_tags_of_every_format() {
+        let tag = |std_key, key: &str, value: Value| Tag::new(std_key, key, value);
+        let mut builder = MetadataBuilder::new();
+        builder
+            .add_tag(tag(Some(StandardTagKey::TrackTitle), "TITLE", Value::from("Song")))
+            .add_tag(tag(Some(StandardTagKey::Artist), "TPE1", Value::from("Band")))
+            .add_tag(tag(Some(StandardTagKey::Bpm), "TBPM", Value::from("300")))
+            .add_tag(tag(
+                Some(StandardTagKey::ReplayGainTrackGain),
+                "REPLAYGAIN_TRACK_GAIN",
+                Value::from("-6.02 dB"),
+            ))
+            .add_tag(tag(Some(StandardTagKey::ReplayGainTrackPeak), "REPLAYGAIN_TRACK_PEAK", Value::from("0.9")))
+            .add_tag(tag(None, "TXXX:MUSIC_RIDER_DIFFICULTY", Value::from("7")));
+        let id3 = builder.metadata();
+        let mut builder = MetadataBuilder::new();
+        builder
+            .add_tag(tag(None, "tmpo", Value::UnsignedInt(128)))
+            .add_tag(tag(Some(StandardTagKey::Bpm), "BPM", Value::from("not a number")));
+        let mp4 = builder.metadata();
+
+        let metadata = TrackMetadata::from_revisions([&id3, &mp4]);
+        assert_eq!(metadata.to_string(), "Band - Song");
+        assert_eq!(metadata.bpm, Some(128.));
+        assert_eq!(metadata.difficulty, Some(7.));
+        assert!((metadata.replay_gain.factor(GainMode::Track) - 0.5).abs() < 0.001);
+        assert_eq!(metadata.replay_gain.factor(GainMode::Off), 1.);
+
+        let loud = ReplayGain {
+            track_gain: Some(6.),
+            track_peak: Some(0.8),
+            ..ReplayGain::default()
+        };
+        assert_eq!(loud.factor(GainMode::Album), 1.25);
+    }
+}
@@ -1,14 +1,33 @@
-use std::{
-    path::PathBuf,
-    sync::mpsc::{Receiver, Sender},
-};
+use anyhow::Context as _;
+use std::{borrow::Cow, path::PathBuf, sync::mpsc::Receiver, time::{Duration, Instant}};
 use symphonia::core::{
+    audio::{AudioBuffer, AudioBufferRef, Signal},
     formats::FormatOptions, meta::MetadataOptions, probe::ProbeResult,
 };
 
+mod clock;
+mod metadata;
 mod output;
 mod scanner;
+mod stream;
+pub use clock::PlaybackClock;
+pub use metadata::{GainMode, TrackMetadata};
+pub use scanner::{Normalization, NormalizationScope, ScanSettings, TempoDetection};
 use output::AudioOutput;
+use stream::{Next, Stream};
+
+use crate::beatmap::Beatmap;
+use crate::chart;
+use crate::pipeline::Pipeline;
+use crate::profile::RideProfile;
+use crate::ride_chart::{self, RideChart};
+use crate::zones;
+
+/// how often to check whether the ride chart of the playing track was edited
+const CHART_POLL: Duration = Duration::from_secs(1);
+
+/// extensions of the files this build of symphonia can play
+pub const EXTENSIONS: [&str; 5] = ["flac", "ogg", "oga", "wav", "mka"];
 
 pub struct Audio {
     path: PathBuf,
@@ -16,44 +35,95 @@ pub struct Audio {
     current_track: usize,
     tracks: Vec<PathBuf>,
     audio_output: Option<Box<dyn AudioOutput>>,
-    scale: f64,
-    offset: f32,
+    settings: ScanSettings,
+    use_cache: bool,
+    pipeline: Pipeline,
+    show_levels: bool,
+    /// how much the note density of a beatmap weighs into the levels
+    beatmap_weight: f64,
+    /// the beatmap of every track, if it has one
+    beatmaps: Vec<Option<Beatmap>>,
+    /// the normalization shared by every track, when normalizing per album
+    album_window: Option<scanner::Window>,
+    clock: PlaybackClock,
+    replay_gain: GainMode,
+    /// the FTP to lay training zones over the levels for, if any
+    zones: Option<f64>,
 }
 
 impl Audio {
-    pub fn new(path: PathBuf, scale: f64, offset: f32) -> Self {
+    pub fn new(
+        path: PathBuf,
+        settings: ScanSettings,
+        use_cache: bool,
+        pipeline: Pipeline,
+        show_levels: bool,
+        beatmap_weight: f64,
+        clock: PlaybackClock,
+    ) -> Self {
         let mut audio = Audio {
             path: path.clone(),
             album_length: 0,
             current_track: 0,
             tracks: Vec::new(),
             audio_output: None,
-            scale,
-            offset,
+            settings,
+            use_cache,
+            pipeline,
+            show_levels,
+            beatmap_weight,
+            beatmaps: Vec::new(),
+            album_window: None,
+            clock,
+            replay_gain: GainMode::Off,
+            zones: None,
         };
         audio.tracks = audio.files();
         audio.album_length = audio.tracks.len();
+        audio.beatmaps = Beatmap::find_all(&audio.tracks);
         audio
     }
 
     fn files(&self) -> Vec<PathBuf> {
-        let mut flacs = Vec::new();
+        let mut files = Vec::new();
         if self.path.is_file() {
             return vec![self.path.clone()];
         }
         if let Ok(entries) = std::fs::read_dir(&self.path) {
             for entry in entries.flatten() {
                 if let Some(ext) = entry.path().extension()
-                    && ext == "flac"
+                    && EXTENSIONS.iter().any(|known| ext.eq_ignore_ascii_case(known))
                     && let Some(name) = entry.path().file_name()
                     && let Some(name_str) = name.to_str()
                 {
-                    flacs.push(self.path.join(name_str));
+                    files.push(self.path.join(name_str));
                 }
             }
         }
-        flacs.sort();
-        flacs
+        files.sort();
+        files
+    }
+
+    /// play tracks back at their ReplayGain
+    pub fn set_replay_gain(&mut self, mode: GainMode) {
+        self.replay_gain = mode;
+    }
+
+    /// lay training zones for a rider with this FTP over every track
+    pub fn set_zones(&mut self, ftp: f64) {
+        self.zones = Some(ftp);
+    }
+
+    /// scan every track up front, so they can share one normalization
+    pub fn normalize_album(&mut self) -> anyhow::Result<()> {
+        let profiles = self
+            .tracks
+            .iter()
+            .map(|track| scanner::load_or_scan(track, &self.settings, self.use_cache))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let profiles: Vec<_> = profiles.iter().collect();
+        self.album_window = scanner::Window::of(&profiles, self.settings.normalization);
+        Ok(())
     }
 
     pub fn next_track(&mut self) -> Option<PathBuf> {
@@ -73,60 +143,101 @@ impl Audio {
         }
     }
 
+    pub fn tracks(&self) -> &[PathBuf] {
+        &self.tracks
+    }
+
+    /// the profile of a track, with everything but its ride chart applied
+    fn profile(&self, track: usize) -> anyhow::Result<RideProfile> {
+        let mut profile = scanner::load_or_scan(&self.tracks[track], &self.settings, self.use_cache)?;
+        if let Some(window) = &self.album_window {
+            scanner::derive_levels(&mut profile, &self.settings, Some(window));
+        }
+        // beatmaps aren't part of the cache, so a chart dropped next to the track is picked up without rescanning
+        if let Some(beatmap) = &self.beatmaps[track] {
+            beatmap.apply(&mut profile, self.beatmap_weight);
+        }
+        // post-processing is cheap, so it runs on every load rather than being baked into the cache
+        self.pipeline.process(&mut profile);
+        Ok(profile)
+    }
+
+    /// the profile of a track as it would be ridden
+    pub fn ride_profile(&self, track: usize) -> anyhow::Result<RideProfile> {
+        self.with_ride_chart(&self.profile(track)?)
+    }
+
+    /// `profile` with the ride chart of its track applied, if there is one, and the zones on top
+    fn with_ride_chart(&self, profile: &RideProfile) -> anyhow::Result<RideProfile> {
+        let mut profile = profile.clone();
+        if let Some(chart) = RideChart::load(&profile.track.path)? {
+            chart.apply(&mut profile);
+        }
+        if let Some(ftp) = self.zones {
+            zones::apply(&mut profile, ftp);
+        }
+        Ok(profile)
+    }
+
     pub fn play_track(
         &mut self,
-        sender: Sender<(Option<u8>, f64)>,
         shutdown_signal: &mut Receiver<()>,
-        analyzer_choice: String,
     ) -> anyhow::Result<usize> {
-        let probed = get_probe(&self.tracks[self.current_track]);
-        let what = scanner::scan(&self.tracks[self.current_track], self.scale, analyzer_choice)?;
-        let what_size = what.iter().len();
-        let mut format = probed.format;
-        let track = match format
-            .tracks()
-            .iter()
-            .find(|track| track.codec_params.codec != symphonia::core::codecs::CODEC_TYPE_NULL)
+        let probed = get_probe(&self.tracks[self.current_track])?;
+        let base = self.profile(self.current_track)?;
+        if self.show_levels {
+            let width = chart::width();
+            let before = base.feature("raw_level").map_or(&base.levels, |curve| &curve.values);
+            println!("before    {}", chart::sparkline(before, width));
+            println!("after     {}", chart::sparkline(&base.levels, width));
+        }
+        // the ride chart goes on top of everything, so hand written levels are taken literally
+        let profile = self.with_ride_chart(&base).unwrap_or_else(|e| {
+            println!("{e:#}, riding without it");
+            base.clone()
+        });
+        println!("now playing: {}", base.track.metadata);
+        // what `music-rider tag` rated the track, to know what's coming
+        if let (Some(difficulty), Some(average_level)) =
+            (base.track.metadata.difficulty, base.track.metadata.average_level)
         {
-            Some(track) => track.clone(),
-            _ => return Ok(0),
-        };
-
-        let dec_opts: symphonia::core::codecs::DecoderOptions = Default::default();
-        let mut decoder = symphonia::default::get_codecs()
-            .make(&track.codec_params, &dec_opts)
-            .expect("unsupported codec");
-        let track_id = track.id;
-        let mut idx = 0;
+            println!("difficulty {difficulty:.0}/10, average level {:.0}%", average_level * 100.);
+        }
+        let gain = base.track.metadata.replay_gain.factor(self.replay_gain) as f32;
+        let mut watcher = ride_chart::Watcher::new(&base.track.path);
+        let mut last_poll = Instant::now();
+        let mut stream = Stream::new(probed.format)?;
+        self.clock.load(self.current_track, profile);
         loop {
             if shutdown_signal.try_recv().is_ok() {
                 self.current_track = self.album_length;
                 return Ok(0);
             }
-            let packet = match format.next_packet() {
-                Ok(packet) => packet,
-                Err(symphonia::core::errors::Error::ResetRequired) => {
-                    unimplemented!();
-                }
-                Err(_) => {
-                    return Ok(0);
+            if last_poll.elapsed() >= CHART_POLL {
+                last_poll = Instant::now();
+                if watcher.changed() {
+                    match self.with_ride_chart(&base) {
+                        Ok(profile) => {
+                            println!("ride chart changed, reloaded it");
+                            self.clock.update(profile);
+                        }
+                        Err(e) => println!("{e:#}, keeping the previous one"),
+                    }
                 }
-            };
-
-            while !format.metadata().is_latest() {
-                format.metadata().pop();
             }
+            let packet = match stream.next()? {
+                Next::Packet(packet) => packet,
+                // the new stream plays on where the last one ended, with a decoder of its own
+                Next::Chained => continue,
+                Next::End => return Ok(0),
+            };
 
-            if packet.track_id() != track_id {
-                println!(
-                    "oops! Track ID mismatch: expected {}, got {}",
-                    track_id,
-                    packet.track_id()
-                );
-                continue;
+            while !stream.format().metadata().is_latest() {
+                stream.format().metadata().pop();
             }
 
-            match decoder.decode(&packet) {
+            let position = stream.position(&packet);
+            match stream.decoder().decode(&packet) {
                 Ok(decoded) => {
                     if self.audio_output.is_none() {
                         let spec = *decoded.spec();
@@ -136,20 +247,16 @@ impl Audio {
                     }
 
                     if let Some(audio_output) = self.audio_output.as_mut() {
-                        audio_output.write(decoded.clone()).unwrap();
+                        if gain == 1. {
+                            audio_output.write(decoded.clone()).unwrap();
+                        } else {
+                            let mut louder: AudioBuffer<f32> = decoded.make_equivalent();
+                            decoded.convert(&mut louder);
+                            louder.transform(|sample| sample * gain);
+                            audio_output.write(AudioBufferRef::F32(Cow::Owned(louder))).unwrap();
+                        }
                     }
-                    // magic number: 0.4 = loudness momentary window.
-                    // dividing it by 2 gets us the loudness halfway through the window
-                    // we add this value to the offset which is the number of seconds to offset in addition
-                    // (to account for latency applying this to the bike)
-                    let offset = ((0.4/2.)+self.offset / track.codec_params.sample_rate.unwrap() as f32).round() as usize;
-                    let index = (idx + offset).min(what_size - 1);
-                    let success = sender.send(*what.get(index).unwrap());
-                    if let Err(e) = success {
-                        println!("{e}");
-                        return Ok(0);
-                    }
-                    idx += 1;
+                    self.clock.advance(position);
                 }
                 Err(symphonia::core::errors::Error::IoError(_)) => continue,
                 Err(symphonia::core::errors::Error::DecodeError(_)) => continue,
@@ -162,14 +269,16 @@ impl Audio {
     }
 }
 
-pub fn get_probe(path: &PathBuf) -> ProbeResult {
-    let src = std::fs::File::open(path).expect("failed to open media");
+pub fn get_probe(path: &PathBuf) -> anyhow::Result<ProbeResult> {
+    let src = std::fs::File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
     let mss = symphonia::core::io::MediaSourceStream::new(Box::new(src), Default::default());
     let mut hint = symphonia::core::probe::Hint::new();
-    hint.with_extension("flac");
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
     let meta_opts: MetadataOptions = Default::default();
     let fmt_opts: FormatOptions = Default::default();
     symphonia::default::get_probe()
         .format(&hint, mss, &fmt_opts, &meta_opts)
-        .expect("unsupported format")
+        .with_context(|| format!("unsupported format: {}", path.display()))
 }
\ No newline at end of file
@@ -0,0 +1,56 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use crate::profile::{PROFILE_VERSION, RideProfile};
+
+use super::ScanSettings;
+
+/// load a previously computed profile for the track, or scan it and cache the result
+///
+/// the cache key covers the file itself (path, size and modification time) and everything that
+/// changes the outcome of a scan, so a retagged track or a different analyzer gets rescanned
+pub fn load_or_scan(
+    path: &PathBuf,
+    settings: &ScanSettings,
+    use_cache: bool,
+) -> anyhow::Result<RideProfile> {
+    let cache_path = if use_cache {
+        cache_path(path, settings)
+    } else {
+        None
+    };
+    if let Some(cache_path) = &cache_path
+        && let Ok(profile) = RideProfile::load(cache_path)
+    {
+        return Ok(profile);
+    }
+
+    let profile = super::scan(path, settings)?;
+    if let Some(cache_path) = &cache_path
+        && let Err(e) = profile.save(cache_path)
+    {
+        println!("failed to cache profile for {}: {e}", path.display());
+    }
+    Ok(profile)
+}
+
+fn cache_path(path: &Path, settings: &ScanSettings) -> Option<PathBuf> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    PROFILE_VERSION.hash(&mut hasher);
+    path.canonicalize().ok()?.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    metadata.modified().ok()?.hash(&mut hasher);
+    settings.analyzer.hash(&mut hasher);
+    settings.options.hash(&mut hasher);
+    settings.scale.to_bits().hash(&mut hasher);
+    settings.normalization.hash(&mut hasher);
+    settings.tempo_detection.hash(&mut hasher);
+    settings.sections.hash(&mut hasher);
+    Some(
+        dirs::cache_dir()?
+            .join("music-rider")
+            .join(format!("{:016x}.bin", hasher.finish())),
+    )
+}
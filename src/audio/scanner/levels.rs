@@ -0,0 +1,132 @@
+use crate::analysis::{self, percentile};
+use crate::profile::RideProfile;
+
+use super::ScanSettings;
+
+/// how the analyzer output is stretched over the level range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum Normalization {
+    /// use the analyzer score as is, e.g. the fixed LUFS window of the lufs analyzer
+    Fixed,
+    /// stretch the 5th to 95th percentile of the score over the full range
+    Percentile,
+    /// stretch the loudness range (LRA) of the music over the full range, needs the `lufs` feature
+    Lra,
+}
+
+/// what the normalization looks at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum NormalizationScope {
+    /// every track uses the full range on its own
+    Track,
+    /// tracks share one range, so quiet songs stay easier than loud ones on the same album
+    Album,
+}
+
+/// the loudness range is never stretched thinner than this (in LU), so a brickwalled
+/// track doesn't turn its last decibel of wiggle into the whole level range
+const MIN_LOUDNESS_RANGE: f64 = 6.;
+
+/// the stretch of a feature curve that covers the full level range
+#[derive(Debug, Clone)]
+pub struct Window {
+    feature: &'static str,
+    low: f64,
+    high: f64,
+}
+
+impl Window {
+    /// find the window for the given profiles (a single track, or a whole album)
+    ///
+    /// falls back to the score percentiles when asked for the loudness range of profiles without loudness
+    pub fn of(profiles: &[&RideProfile], normalization: Normalization) -> Option<Window> {
+        let curve = |name: &str| -> Option<Vec<f64>> {
+            let mut values = Vec::new();
+            for profile in profiles {
+                values.extend_from_slice(&profile.feature(name)?.values);
+            }
+            Some(values)
+        };
+        let window = match normalization {
+            Normalization::Fixed => return None,
+            Normalization::Lra => match curve("lufs") {
+                Some(lufs) => {
+                    let (low, high) = loudness_range(&lufs);
+                    let high = high.max(low + MIN_LOUDNESS_RANGE);
+                    Window { feature: "lufs", low, high }
+                }
+                None => return Window::of(profiles, Normalization::Percentile),
+            },
+            Normalization::Percentile => {
+                let scores = curve("score")?;
+                Window {
+                    feature: "score",
+                    low: percentile(&scores, 0.05),
+                    high: percentile(&scores, 0.95),
+                }
+            }
+        };
+        (window.high - window.low > f64::EPSILON).then_some(window)
+    }
+
+    fn apply(&self, value: f64) -> f64 {
+        (value - self.low) / (self.high - self.low)
+    }
+}
+
+/// the 10th and 95th percentile of the gated loudness, whose difference is the loudness range (LRA)
+///
+/// like EBU Tech 3342, but on momentary instead of short-term loudness: everything below -70 LUFS,
+/// or more than 20 LU below the average of what's left, is silence and doesn't count
+pub fn loudness_range(lufs: &[f64]) -> (f64, f64) {
+    let audible: Vec<f64> = lufs.iter().copied().filter(|&lufs| lufs > -70.).collect();
+    if audible.is_empty() {
+        return (-70., -70.);
+    }
+    let power = audible.iter().map(|lufs| 10f64.powf(lufs / 10.)).sum::<f64>() / audible.len() as f64;
+    let gate = 10. * power.log10() - 20.;
+    let gated: Vec<f64> = audible.into_iter().filter(|&lufs| lufs > gate).collect();
+    (
+        percentile(&gated, 0.10),
+        percentile(&gated, 0.95),
+    )
+}
+
+/// (re)derive the level curve of `profile` from its features
+///
+/// `window` stretches the analyzer output first; without one the score is used as is.
+/// levels are held per section if the profile has sections
+pub fn derive_levels(profile: &mut RideProfile, settings: &ScanSettings, window: Option<&Window>) {
+    let values = match window {
+        Some(window) => profile
+            .feature(window.feature)
+            .map(|curve| curve.values.iter().map(|&value| window.apply(value)).collect()),
+        None => profile.feature("score").map(|curve| curve.values.clone()),
+    };
+    profile.levels = values
+        .unwrap_or_default()
+        .into_iter()
+        .map(|value: f64| (value * settings.scale).clamp(0., 1.))
+        .collect();
+    if !profile.sections.is_empty() {
+        profile.levels = analysis::plateau(&profile.levels, &profile.sections, profile.frame_rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loudness_range() {
+        // a quiet passage around -30 LUFS, a loud one around -10, and a silent gap that mustn't count
+        let lufs: Vec<f64> = (0..100)
+            .map(|i| -30. + (i % 5) as f64)
+            .chain((0..100).map(|i| -10. - (i % 5) as f64))
+            .chain(std::iter::repeat_n(-70., 50))
+            .collect();
+        let (low, high) = loudness_range(&lufs);
+        assert!((-30.0..=-26.).contains(&low), "low {low}");
+        assert!((-11.0..=-10.).contains(&high), "high {high}");
+    }
+}
@@ -1,67 +1,164 @@
 use std::path::PathBuf;
 
-use crate::analysis;
+use crate::analysis::{self, AnalyzerOptions, AnalyzerType, BeatTracker, Frames, Segmenter};
+use crate::profile::{RideProfile, Tempo, TrackInfo};
 
 use super::get_probe;
-use symphonia::core::{audio::SampleBuffer, codecs::DecoderOptions, meta::Value};
+use super::metadata::TrackMetadata;
+use super::stream::{Next, Stream};
+use symphonia::core::audio::{Channels, SampleBuffer, SignalSpec};
+
+mod cache;
+mod levels;
+pub use cache::load_or_scan;
+pub use levels::{Normalization, NormalizationScope, Window, derive_levels};
+
+/// number of frames (samples per channel) analyzed at a time, which makes up one profile frame
+pub const FRAME_SIZE: usize = 4096;
+
+/// when to run the beat tracker on a track
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum TempoDetection {
+    /// only for tracks without a BPM tag
+    Auto,
+    /// for every track, preferring the detected tempo over tags
+    Always,
+    /// only use BPM tags
+    Never,
+}
+
+/// everything that decides the outcome of a scan
+#[derive(Debug, Clone)]
+pub struct ScanSettings {
+    pub analyzer: AnalyzerType,
+    pub options: AnalyzerOptions,
+    /// multiplies the analyzer score before it becomes a level
+    pub scale: f64,
+    /// how the analyzer output is stretched over the level range of a track
+    pub normalization: Normalization,
+    pub tempo_detection: TempoDetection,
+    /// hold the level steady within each detected section of the song
+    pub sections: bool,
+}
 
 /// precompute track
-pub fn scan(path: &PathBuf, scale: f64, analyzer_choice: String) -> anyhow::Result<Vec<(Option<u8>, f64)>> {
-    let mut ret = Vec::new();
-    let probed = get_probe(path);
+pub fn scan(path: &PathBuf, settings: &ScanSettings) -> anyhow::Result<RideProfile> {
+    let mut probed = get_probe(path)?;
+    // id3 tags sit in front of the container, everything else comes with the format
+    let mut revisions = Vec::new();
+    if let Some(metadata) = probed.metadata.get() {
+        revisions.extend(metadata.current().cloned());
+    }
     let mut format = probed.format;
-    let bpm = if let Some(bpm) = format.metadata().current().unwrap().tags().iter().find(|i| i.key == "BPM") {
-        match &bpm.value {
-            Value::String(val) => Some(val.clone().parse::<u8>().unwrap()),
-            _ => None
-        }
-    } else {
-        None
-    };
-    let track = format.default_track().unwrap();
-    
-    let dec_opts: DecoderOptions = Default::default();
+    revisions.extend(format.metadata().current().cloned());
+    let metadata = TrackMetadata::from_revisions(&revisions);
+    let bpm = metadata.bpm;
+    let mut stream = Stream::new(format)?;
+    let track = stream.track();
+
     let sample_rate = track.codec_params.sample_rate.unwrap_or(44100);
-    let channels = if let Some(layout) = track.codec_params.channel_layout {
-        match layout {
-            symphonia::core::audio::Layout::Mono => 1,
-            symphonia::core::audio::Layout::Stereo => 2,
-            symphonia::core::audio::Layout::TwoPointOne => 3,
-            symphonia::core::audio::Layout::FivePointOne => 6,
-        }
-    } else {
-        2
-    };
+    let channels = track
+        .codec_params
+        .channels
+        .or(track.codec_params.channel_layout.map(|layout| layout.into_channels()))
+        .unwrap_or(Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+    let spec = SignalSpec::new(sample_rate, channels);
 
-    let analyzer_type = match analyzer_choice.as_str() {
-        "fft" => analysis::AnalyzerType::Fft,
-        _ => analysis::AnalyzerType::Lufs
+    let mut analyzer = analysis::get_analyzer(settings.analyzer, spec, &settings.options)?;
+    let mut beat_tracker = match settings.tempo_detection {
+        TempoDetection::Always => Some(BeatTracker::new(sample_rate)),
+        TempoDetection::Auto if bpm.is_none() => Some(BeatTracker::new(sample_rate)),
+        _ => None,
     };
+    let mut segmenter = settings.sections.then(|| Segmenter::new(sample_rate));
 
-    let mut analyzer = analysis::get_analyzer(analyzer_type, sample_rate, channels, scale)?;
-
-    let mut decoder = symphonia::default::get_codecs()
-        .make(&track.codec_params, &dec_opts)
-        .expect("unsupported codec");
-
-    let track_id = track.id;
+    let mut profile = RideProfile::new(
+        TrackInfo {
+            path: path.clone(),
+            sample_rate,
+            channels: channels.count(),
+            duration: 0.,
+            metadata,
+        },
+        bpm.map(Tempo::from_tag),
+        sample_rate as f64 / FRAME_SIZE as f64,
+    );
+    // decoded packets don't line up with profile frames, so samples are carried over until a frame is full
+    let frame_len = FRAME_SIZE * channels.count();
+    let mut pending: Vec<f32> = Vec::with_capacity(frame_len * 2);
+    let mut total_frames = 0;
+    let mut analyzed_frames = 0;
     println!("Scanning track for peaks..");
-    while let Ok(packet) = format.next_packet() {
-        if packet.track_id() != track_id {
-            continue;
-        }
+    loop {
+        let packet = match stream.next()? {
+            Next::Packet(packet) => packet,
+            Next::Chained => {
+                // a new stream starts within the file (e.g. chained ogg). it plays right after the last one,
+                // so the timeline carries on, but the signal breaks off: the analyzers forget the audio they
+                // buffered. the samples still pending are part of the timeline, and go into the next frame
+                analyzer.reset();
+                if let Some(beat_tracker) = beat_tracker.as_mut() {
+                    beat_tracker.reset();
+                }
+                if let Some(segmenter) = segmenter.as_mut() {
+                    segmenter.reset();
+                }
+                continue;
+            }
+            Next::End => break,
+        };
 
-        match decoder.decode(&packet) {
+        match stream.decoder().decode(&packet) {
             Ok(decoded) => {
                 let mut sample: SampleBuffer<f32> =
                     SampleBuffer::new(decoded.capacity() as u64, *decoded.spec());
                 sample.copy_interleaved_ref(decoded.clone());
-                let score = analyzer.freq_score(sample.samples().to_owned())?;
-                ret.push((bpm, score));
+                total_frames += decoded.frames();
+                pending.extend_from_slice(sample.samples());
+                let mut start = 0;
+                while pending.len() - start >= frame_len {
+                    let frames = Frames {
+                        samples: &pending[start..start + frame_len],
+                        spec,
+                        timestamp: analyzed_frames as f64 / sample_rate as f64,
+                    };
+                    for (name, value) in analyzer.analyze(&frames)? {
+                        profile.push_feature(name, value);
+                    }
+                    if let Some(beat_tracker) = beat_tracker.as_mut() {
+                        profile.push_feature("onset", beat_tracker.push(&frames));
+                    }
+                    if let Some(segmenter) = segmenter.as_mut() {
+                        segmenter.push(&frames);
+                    }
+                    analyzed_frames += FRAME_SIZE;
+                    start += frame_len;
+                }
+                pending.drain(..start);
             }
             Err(_) => break,
         }
     }
 
-    Ok(ret)
+    for curve in analyzer.finish()? {
+        match profile.features.iter_mut().find(|c| c.name == curve.name) {
+            Some(existing) => *existing = curve,
+            None => profile.features.push(curve),
+        }
+    }
+
+    if let Some(tempo) = beat_tracker.and_then(|beat_tracker| beat_tracker.finish()) {
+        profile.tempo = Some(tempo);
+    }
+
+    profile.track.duration = total_frames as f64 / sample_rate as f64;
+    let window = Window::of(&[&profile], settings.normalization);
+    derive_levels(&mut profile, settings, window.as_ref());
+
+    if let Some(segmenter) = segmenter {
+        profile.sections = segmenter.finish(&profile.levels, profile.frame_rate);
+        profile.levels = analysis::plateau(&profile.levels, &profile.sections, profile.frame_rate);
+    }
+
+    Ok(profile)
 }
@@ -0,0 +1,215 @@
+//! the packets of a file's audio track, across chained streams (e.g. chained ogg), which start over with
+//! new tracks and timestamps from 0 but play right after each other
+
+use anyhow::Context as _;
+use symphonia::core::codecs::{CODEC_TYPE_NULL, Decoder, DecoderOptions};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatReader, Packet, Track};
+
+pub struct Stream {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track: Track,
+    /// seconds into the file the current chained stream starts at
+    offset: f64,
+    /// seconds into the file the last packet ended at
+    end: f64,
+}
+
+/// what came next out of the file
+pub enum Next {
+    Packet(Packet),
+    /// a chained stream started, and has its own decoder now
+    Chained,
+    End,
+}
+
+/// the first track with audio in it, and a decoder for it
+fn open(format: &dyn FormatReader) -> anyhow::Result<(Track, Box<dyn Decoder>)> {
+    let track = format
+        .tracks()
+        .iter()
+        .find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+        .context("no audio track found")?
+        .clone();
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .context("unsupported codec")?;
+    Ok((track, decoder))
+}
+
+impl Stream {
+    pub fn new(format: Box<dyn FormatReader>) -> anyhow::Result<Self> {
+        let (track, decoder) = open(format.as_ref())?;
+        Ok(Stream {
+            format,
+            decoder,
+            track,
+            offset: 0.,
+            end: 0.,
+        })
+    }
+
+    /// the track being decoded, which changes when a chained stream starts
+    pub fn track(&self) -> &Track {
+        &self.track
+    }
+
+    pub fn format(&mut self) -> &mut dyn FormatReader {
+        self.format.as_mut()
+    }
+
+    pub fn decoder(&mut self) -> &mut dyn Decoder {
+        self.decoder.as_mut()
+    }
+
+    /// the next packet of the track, skipping those of other tracks
+    pub fn next(&mut self) -> anyhow::Result<Next> {
+        loop {
+            match self.format.next_packet() {
+                Ok(packet) if packet.track_id() == self.track.id => {
+                    self.end = self.position(&packet) + self.seconds(packet.dur());
+                    return Ok(Next::Packet(packet));
+                }
+                Ok(_) => continue,
+                Err(Error::ResetRequired) => {
+                    (self.track, self.decoder) = open(self.format.as_ref())?;
+                    self.offset = self.end;
+                    return Ok(Next::Chained);
+                }
+                // anything else ends the file for good
+                Err(_) => return Ok(Next::End),
+            }
+        }
+    }
+
+    /// seconds into the file `packet` starts at
+    pub fn position(&self, packet: &Packet) -> f64 {
+        self.offset + self.seconds(packet.ts())
+    }
+
+    fn seconds(&self, timestamp: u64) -> f64 {
+        match self.track.codec_params.time_base {
+            Some(time_base) => {
+                let time = time_base.calc_time(timestamp);
+                time.seconds as f64 + time.frac
+            }
+            None => timestamp as f64 / self.track.codec_params.sample_rate.unwrap_or(44_100) as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use symphonia::core::audio::Channels;
+    use symphonia::core::codecs::{CODEC_TYPE_PCM_S16LE, CodecParameters};
+    use symphonia::core::formats::{Cue, FormatOptions, SeekMode, SeekTo, SeekedTo};
+    use symphonia::core::io::MediaSourceStream;
+    use symphonia::core::meta::{Metadata, MetadataLog};
+    use symphonia::core::units::TimeBase;
+
+    /// two chained streams of 16 bit mono PCM at 8 kHz, each packet a tenth of a second
+    struct Chained {
+        tracks: Vec<Track>,
+        chained: Vec<Track>,
+        packets: VecDeque<Option<Packet>>,
+        metadata: MetadataLog,
+    }
+
+    fn track(id: u32) -> Track {
+        let mut params = CodecParameters::new();
+        params
+            .for_codec(CODEC_TYPE_PCM_S16LE)
+            .with_sample_rate(8_000)
+            .with_channels(Channels::FRONT_LEFT)
+            .with_bits_per_coded_sample(16)
+            .with_max_frames_per_packet(800)
+            .with_time_base(TimeBase::new(1, 8_000));
+        Track::new(id, params)
+    }
+
+    fn packet(track: u32, ts: u64) -> Option<Packet> {
+        Some(Packet::new_from_slice(track, ts, 800, &[0; 1600]))
+    }
+
+    impl FormatReader for Chained {
+        fn try_new(
+            _: MediaSourceStream,
+            _: &FormatOptions,
+        ) -> symphonia::core::errors::Result<Self> {
+            Err(Error::Unsupported("made up in tests"))
+        }
+
+        fn cues(&self) -> &[Cue] {
+            &[]
+        }
+
+        fn metadata(&mut self) -> Metadata<'_> {
+            self.metadata.metadata()
+        }
+
+        fn seek(&mut self, _: SeekMode, _: SeekTo) -> symphonia::core::errors::Result<SeekedTo> {
+            Err(Error::Unsupported("made up in tests"))
+        }
+
+        fn tracks(&self) -> &[Track] {
+            &self.tracks
+        }
+
+        fn next_packet(&mut self) -> symphonia::core::errors::Result<Packet> {
+            match self.packets.pop_front() {
+                Some(Some(packet)) => Ok(packet),
+                Some(None) => {
+                    self.tracks = std::mem::take(&mut self.chained);
+                    Err(Error::ResetRequired)
+                }
+                None => Err(Error::IoError(std::io::ErrorKind::UnexpectedEof.into())),
+            }
+        }
+
+        fn into_inner(self: Box<Self>) -> MediaSourceStream {
+            unreachable!("the source is never taken back in tests")
+        }
+    }
+
+    #[test]
+    fn test_chained_streams_carry_on() -> anyhow::Result<()> {
+        let format = Chained {
+            tracks: vec![track(0)],
+            chained: vec![track(1)],
+            // the second stream starts over from 0, with a track of its own
+            packets: [
+                packet(0, 0),
+                packet(0, 800),
+                None,
+                packet(0, 0),
+                packet(1, 0),
+                packet(1, 800),
+            ]
+            .into(),
+            metadata: MetadataLog::default(),
+        };
+        let mut stream = Stream::new(Box::new(format))?;
+        let mut positions = Vec::new();
+        let mut chained = 0;
+        loop {
+            match stream.next()? {
+                Next::Packet(packet) => {
+                    assert_eq!(stream.decoder().decode(&packet)?.frames(), 800);
+                    positions.push(stream.position(&packet));
+                }
+                Next::Chained => chained += 1,
+                Next::End => break,
+            }
+        }
+        assert_eq!(chained, 1);
+        assert_eq!(stream.track().id, 1);
+        assert_eq!(positions.len(), 4);
+        for (position, expected) in positions.iter().zip([0., 0.1, 0.2, 0.3]) {
+            assert!((position - expected).abs() < 1e-9, "{positions:?}");
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::stretch;
+use crate::profile::{FeatureCurve, RideProfile, Tempo, TempoSegment, TempoSource};
+
+mod osu;
+mod stepmania;
+
+/// seconds of notes counted on either side of a frame for the note density
+const DENSITY_SECONDS: f64 = 1.;
+
+/// hand timed beats and notes from a rhythm game chart of the track
+#[derive(Debug, Clone, Default)]
+pub struct Beatmap {
+    /// tempo changes, sorted by start. the first one starts at the first beat
+    pub timing: Vec<TempoSegment>,
+    /// note times in seconds, sorted
+    pub notes: Vec<f64>,
+    /// the audio file the chart was made for, as written in the chart
+    pub music: Option<String>,
+}
+
+impl Beatmap {
+    /// the densest beatmap next to each of `tracks` that was made for it, reading every directory once
+    ///
+    /// a beatmap belongs to the track if it's named after it (`song.sm` for `song.flac`), or if the
+    /// audio file it was made for has the same name as the track, give or take the extension
+    pub fn find_all(tracks: &[PathBuf]) -> Vec<Option<Beatmap>> {
+        let mut directories: HashMap<&Path, Vec<(PathBuf, Beatmap)>> = HashMap::new();
+        tracks
+            .iter()
+            .map(|track| {
+                let directory = match track.parent() {
+                    Some(parent) if !parent.as_os_str().is_empty() => parent,
+                    _ => Path::new("."),
+                };
+                let beatmaps = directories.entry(directory).or_insert_with(|| Beatmap::load_all(directory));
+                Beatmap::find(track, beatmaps)
+            })
+            .collect()
+    }
+
+    /// the densest of `beatmaps` made for `track`
+    fn find(track: &Path, beatmaps: &[(PathBuf, Beatmap)]) -> Option<Beatmap> {
+        let stem = track.file_stem()?.to_string_lossy().to_lowercase();
+        let named_after =
+            |name: &Path| name.file_stem().is_some_and(|name| name.to_string_lossy().to_lowercase() == stem);
+        beatmaps
+            .iter()
+            .filter(|(path, beatmap)| {
+                named_after(path)
+                    || beatmap
+                        .music
+                        .as_deref()
+                        .is_some_and(|music| named_after(&PathBuf::from(music)))
+            })
+            .map(|(_, beatmap)| beatmap)
+            .max_by_key(|beatmap| beatmap.notes.len())
+            .cloned()
+    }
+
+    /// every beatmap in `directory` with some timing in it
+    fn load_all(directory: &Path) -> Vec<(PathBuf, Beatmap)> {
+        let Ok(entries) = std::fs::read_dir(directory) else {
+            return Vec::new();
+        };
+        entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter_map(|path| Beatmap::load(&path).map(|beatmap| (path, beatmap)))
+            .filter(|(_, beatmap)| !beatmap.timing.is_empty())
+            .collect()
+    }
+
+    /// parse a `.sm`, `.ssc` or `.osu` file, `None` if it's none of those or can't be read
+    fn load(path: &Path) -> Option<Beatmap> {
+        let extension = path.extension()?.to_string_lossy().to_lowercase();
+        if !matches!(extension.as_str(), "sm" | "ssc" | "osu") {
+            return None;
+        }
+        let bytes = std::fs::read(path).ok()?;
+        let text = String::from_utf8_lossy(&bytes);
+        match extension.as_str() {
+            "osu" => Some(osu::parse(&text)),
+            _ => Some(stepmania::parse(&text)),
+        }
+    }
+
+    /// the tempo of the chart, with every beat up to `duration` seconds
+    pub fn tempo(&self, duration: f64) -> Option<Tempo> {
+        let first = self.timing.first()?;
+        // the tempo that plays the longest is the tempo of the track
+        let bpm = self
+            .timing
+            .iter()
+            .enumerate()
+            .map(|(i, segment)| {
+                let end = self.timing.get(i + 1).map_or(duration, |next| next.start);
+                (segment.bpm, end - segment.start)
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(first.bpm, |(bpm, _)| bpm);
+
+        // walk back from the first beat to the start of the track, then forward to its end
+        let mut beat = first.start;
+        let step = 60. / first.bpm;
+        while beat - step >= 0. {
+            beat -= step;
+        }
+        let mut beats = Vec::new();
+        while beat < duration {
+            beats.push(beat);
+            let bpm = self
+                .timing
+                .iter()
+                .take_while(|segment| segment.start <= beat + 1e-6)
+                .last()
+                .map_or(first.bpm, |segment| segment.bpm);
+            beat += 60. / bpm;
+        }
+
+        Some(Tempo {
+            bpm,
+            source: TempoSource::Beatmap,
+            confidence: 1.,
+            beats,
+            map: if self.timing.len() > 1 { self.timing.clone() } else { Vec::new() },
+        })
+    }
+
+    /// notes per second around each of `frames` profile frames
+    pub fn density(&self, frames: usize, frame_rate: f64) -> Vec<f64> {
+        let (mut start, mut end) = (0, 0);
+        (0..frames)
+            .map(|frame| {
+                let time = frame as f64 / frame_rate;
+                while start < self.notes.len() && self.notes[start] < time - DENSITY_SECONDS {
+                    start += 1;
+                }
+                while end < self.notes.len() && self.notes[end] < time + DENSITY_SECONDS {
+                    end += 1;
+                }
+                (end - start.min(end)) as f64 / (2. * DENSITY_SECONDS)
+            })
+            .collect()
+    }
+
+    /// take over the timing of the track, add the `note_density` feature, and blend it into the
+    /// levels by `weight` (from 0.0 to 1.0)
+    pub fn apply(&self, profile: &mut RideProfile, weight: f64) {
+        if let Some(tempo) = self.tempo(profile.track.duration) {
+            profile.tempo = Some(tempo);
+        }
+        let density = self.density(profile.levels.len(), profile.frame_rate);
+        if weight > 0. && !self.notes.is_empty() {
+            let intensity = stretch(&density, 0.05, 0.95);
+            for (level, intensity) in profile.levels.iter_mut().zip(intensity) {
+                *level = *level * (1. - weight) + intensity * weight;
+            }
+        }
+        profile.features.retain(|curve| curve.name != "note_density");
+        profile.features.push(FeatureCurve {
+            name: String::from("note_density"),
+            values: density,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let beatmap = |music: Option<&str>, notes: usize| Beatmap {
+            timing: vec![TempoSegment { start: 0., bpm: 120. }],
+            notes: vec![0.; notes],
+            music: music.map(str::to_string),
+        };
+        let beatmaps = vec![
+            (PathBuf::from("album/Song.sm"), beatmap(None, 10)),
+            (PathBuf::from("album/charts.osu"), beatmap(Some("song.mp3"), 20)),
+            (PathBuf::from("album/other.sm"), beatmap(Some("other.ogg"), 30)),
+        ];
+        let found = Beatmap::find(Path::new("album/song.flac"), &beatmaps);
+        assert_eq!(found.map(|beatmap| beatmap.notes.len()), Some(20));
+        assert!(Beatmap::find(Path::new("album/unknown.flac"), &beatmaps).is_none());
+    }
+}
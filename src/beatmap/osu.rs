@@ -0,0 +1,79 @@
+use super::Beatmap;
+use crate::profile::TempoSegment;
+
+/// parse an osu! `.osu` file
+///
+/// uninherited `[TimingPoints]` (`time,beat length,...` in milliseconds) set the tempo, and every
+/// one of the `[HitObjects]` (`x,y,time,...`) is a note
+pub fn parse(text: &str) -> Beatmap {
+    let mut section = "";
+    let mut beatmap = Beatmap::default();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = name;
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        match section {
+            "General" => {
+                if let Some((key, music)) = line.split_once(':')
+                    && key.trim() == "AudioFilename"
+                {
+                    beatmap.music = Some(music.trim().to_string());
+                }
+            }
+            "TimingPoints" => {
+                let (Some(Ok(time)), Some(Ok(beat_length))) = (
+                    fields.first().map(|time| time.parse::<f64>()),
+                    fields.get(1).map(|length| length.parse::<f64>()),
+                ) else {
+                    continue;
+                };
+                // inherited points only change the slider speed, and have a negative beat length
+                let uninherited = fields.get(6).map_or(beat_length > 0., |field| *field == "1");
+                if uninherited && beat_length > 0. {
+                    beatmap.timing.push(TempoSegment {
+                        start: time / 1000.,
+                        bpm: 60_000. / beat_length,
+                    });
+                }
+            }
+            "HitObjects" => {
+                if let Some(Ok(time)) = fields.get(2).map(|time| time.parse::<f64>()) {
+                    beatmap.notes.push(time / 1000.);
+                }
+            }
+            _ => {}
+        }
+    }
+    beatmap.timing.sort_by(|a, b| a.start.total_cmp(&b.start));
+    beatmap.notes.sort_by(f64::total_cmp);
+    beatmap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let beatmap = parse(
+            "osu file format v14\n\n[General]\nAudioFilename: audio.mp3\n\n[TimingPoints]\n\
+             250,500,4,2,0,60,1,0\n4250,-50,4,2,0,60,0,0\n8250,375,4,2,0,60,1,0\n\n\
+             [HitObjects]\n256,192,250,1,0,0:0:0:0:\n256,192,750,1,0,0:0:0:0:\n256,192,8250,5,0,0:0:0:0:\n",
+        );
+        assert_eq!(beatmap.music.as_deref(), Some("audio.mp3"));
+        assert_eq!(beatmap.timing.len(), 2);
+        assert_eq!(beatmap.timing[0].bpm, 120.);
+        assert_eq!(beatmap.timing[1].bpm, 160.);
+        assert_eq!(beatmap.notes, vec![0.25, 0.75, 8.25]);
+
+        let tempo = beatmap.tempo(10.).unwrap();
+        assert_eq!(tempo.bpm, 120.);
+        assert_eq!(tempo.beats[..2], [0.25, 0.75]);
+        assert_eq!(beatmap.density(3, 1.), vec![1., 1., 0.]);
+    }
+}
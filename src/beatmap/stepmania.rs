@@ -0,0 +1,151 @@
+use super::Beatmap;
+use crate::profile::TempoSegment;
+
+/// a single difficulty of the song
+#[derive(Default)]
+struct Chart {
+    notes: String,
+}
+
+/// parse a StepMania `.sm` or `.ssc` file
+///
+/// both are lists of `#KEY:value;` tags. beats are counted from `#OFFSET` (the negated time of
+/// beat 0 in seconds), the tempo comes from `#BPMS` (`beat=bpm,...`) and `#STOPS` (`beat=seconds,...`)
+/// pause it. `.sm` files put every chart in a `#NOTES` tag, `.ssc` files start each with `#NOTEDATA`
+pub fn parse(text: &str) -> Beatmap {
+    let text: String = text
+        .lines()
+        .map(|line| line.split_once("//").map_or(line, |(line, _)| line))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut offset = 0.;
+    let mut bpms = Vec::new();
+    let mut stops = Vec::new();
+    let mut music = None;
+    let mut charts: Vec<Chart> = Vec::new();
+    for tag in text.split('#').skip(1) {
+        let tag = tag.split_once(';').map_or(tag, |(tag, _)| tag);
+        let Some((key, value)) = tag.split_once(':') else {
+            continue;
+        };
+        match key.trim().to_uppercase().as_str() {
+            "OFFSET" => offset = value.trim().parse().unwrap_or(0.),
+            "BPMS" => bpms = pairs(value),
+            "STOPS" => stops = pairs(value),
+            "MUSIC" => music = Some(value.trim().to_string()).filter(|music| !music.is_empty()),
+            "NOTEDATA" => charts.push(Chart::default()),
+            "NOTES" => {
+                // .sm charts lead with type:description:difficulty:meter:radar values
+                let notes = match value.splitn(6, ':').collect::<Vec<_>>().as_slice() {
+                    [.., notes] if value.matches(':').count() >= 5 => notes.to_string(),
+                    _ => value.to_string(),
+                };
+                match charts.last_mut() {
+                    Some(chart) if chart.notes.is_empty() => chart.notes = notes,
+                    _ => charts.push(Chart { notes }),
+                }
+            }
+            _ => {}
+        }
+    }
+    bpms.sort_by(|a, b| a.0.total_cmp(&b.0));
+    bpms.retain(|&(_, bpm)| bpm > 0.);
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let time = |beat: f64| beat_time(beat, -offset, &bpms, &stops);
+    let timing = bpms
+        .iter()
+        .map(|&(beat, bpm)| TempoSegment {
+            start: time(beat),
+            bpm,
+        })
+        .collect();
+    // the densest chart says the most about how busy the song is
+    let mut notes: Vec<f64> = charts
+        .iter()
+        .map(|chart| note_beats(&chart.notes))
+        .max_by_key(Vec::len)
+        .unwrap_or_default()
+        .into_iter()
+        .map(time)
+        .collect();
+    notes.sort_by(f64::total_cmp);
+
+    Beatmap {
+        timing,
+        notes,
+        music,
+    }
+}
+
+/// `beat=value,beat=value,...`
+fn pairs(value: &str) -> Vec<(f64, f64)> {
+    value
+        .split(',')
+        .filter_map(|pair| {
+            let (beat, value) = pair.split_once('=')?;
+            Some((beat.trim().parse().ok()?, value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// the time of `beat` in seconds, with beat 0 at `start`
+fn beat_time(beat: f64, start: f64, bpms: &[(f64, f64)], stops: &[(f64, f64)]) -> f64 {
+    let mut time = start;
+    let mut position = 0.;
+    let mut bpm = bpms.first().map_or(120., |&(_, bpm)| bpm);
+    for &(change, next_bpm) in bpms.iter().skip_while(|&&(change, _)| change <= 0.) {
+        if change >= beat {
+            break;
+        }
+        time += (change - position) * 60. / bpm;
+        position = change;
+        bpm = next_bpm;
+    }
+    time += (beat - position) * 60. / bpm;
+    time + stops
+        .iter()
+        .filter(|&&(stop, _)| stop < beat)
+        .map(|&(_, seconds)| seconds)
+        .sum::<f64>()
+}
+
+/// the beat of every arrow in a chart: measures are split by commas, and every measure is split
+/// evenly into rows of one character per column
+fn note_beats(notes: &str) -> Vec<f64> {
+    let mut beats = Vec::new();
+    for (measure, rows) in notes.split(',').enumerate() {
+        let rows: Vec<&str> = rows.split_whitespace().collect();
+        for (row, columns) in rows.iter().enumerate() {
+            // taps, hold heads and roll heads start a note
+            let arrows = columns.chars().filter(|column| matches!(column, '1' | '2' | '4')).count();
+            let beat = 4. * (measure as f64 + row as f64 / rows.len() as f64);
+            beats.extend(std::iter::repeat_n(beat, arrows));
+        }
+    }
+    beats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let beatmap = parse(
+            "#TITLE:song;\n#MUSIC:song.ogg;\n#OFFSET:-0.5;\n#BPMS:0.000=120.000,8.000=60.000;\n\
+             #NOTES:\n     dance-single:\n     :\n     Hard:\n     9:\n     0,0,0,0,0:\n\
+             1000\n0100\n0010\n0001\n,  // measure 1\n1001\n0000\n0000\n0000\n,\n0000\n0000\n0000\n1000\n;\n",
+        );
+        assert_eq!(beatmap.music.as_deref(), Some("song.ogg"));
+        assert_eq!(beatmap.timing.len(), 2);
+        assert_eq!(beatmap.timing[0].start, 0.5);
+        // 8 beats at 120 bpm
+        assert_eq!(beatmap.timing[1].start, 4.5);
+        assert_eq!(beatmap.notes.len(), 7);
+        assert_eq!(&beatmap.notes[..4], &[0.5, 1., 1.5, 2.]);
+        // beat 11 of measure 3 is three beats at 60 bpm past the tempo change
+        assert_eq!(beatmap.notes.last(), Some(&7.5));
+    }
+}
@@ -0,0 +1,286 @@
+//! measuring how long the equipment takes to respond to a new level, instead of tuning `--offset` by feel,
+//! and how much power its levels take at different cadences
+
+use std::io::Write as _;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use kondis::Equipment;
+
+use crate::power::Point;
+
+/// the grid commanded and measured values are compared on, in seconds
+const RESOLUTION: f64 = 0.1;
+/// how long to ride the low level before the steps start, so the rider can settle into a cadence
+const WARMUP: f64 = 10.;
+/// how often the equipment is asked for a reading during the calibration ride
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+/// how long readings are ignored after the power sweep moves on, while the rider finds the new cadence
+const SETTLE: f64 = 4.;
+/// how well the response has to line up with the steps for the estimate to be trusted
+const MIN_CORRELATION: f64 = 0.3;
+
+/// levels to alternate between, for steps of uneven length so no two lags line up equally well
+#[derive(Debug, Clone)]
+pub struct Steps {
+    /// when each step ends, and the level during it
+    schedule: Vec<(f64, i16)>,
+}
+
+impl Steps {
+    pub fn new(low: i16, high: i16, count: usize, seconds: f64) -> Self {
+        let mut schedule = vec![(WARMUP, low)];
+        let mut end = WARMUP;
+        // a small linear congruential generator, so every calibration rides the same steps
+        let mut state: u32 = 0x2545_f491;
+        for step in 0..count {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let stretch = 0.5 + (state >> 8) as f64 / (1 << 24) as f64;
+            end += seconds * stretch;
+            schedule.push((end, if step % 2 == 0 { high } else { low }));
+        }
+        Steps { schedule }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.schedule.last().map_or(0., |&(end, _)| end)
+    }
+
+    pub fn level_at(&self, time: f64) -> Option<i16> {
+        self.schedule
+            .iter()
+            .find(|&&(end, _)| time < end)
+            .map(|&(_, level)| level)
+    }
+}
+
+/// what the equipment reported at some point of the calibration ride
+#[derive(Debug, Clone, Copy)]
+pub struct Sample {
+    pub time: f64,
+    pub power: f64,
+    pub resistance: f64,
+}
+
+/// how late the equipment follows the steps
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    /// in seconds
+    pub latency: f64,
+    /// how well the delayed steps match the response, from -1 to 1
+    pub correlation: f64,
+    /// whether the resistance reading matched better than the power reading
+    pub from_resistance: bool,
+}
+
+/// ride the steps, and collect what the equipment reports
+///
+/// stops early, with what was collected so far, when something arrives on `shutdown_rx`
+pub async fn ride(
+    equipment: &dyn Equipment,
+    steps: &Steps,
+    shutdown_rx: &Receiver<()>,
+) -> anyhow::Result<Vec<Sample>> {
+    let mut samples = Vec::new();
+    let mut sent = None;
+    let start = Instant::now();
+    let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let time = start.elapsed().as_secs_f64();
+        let Some(level) = steps.level_at(time) else {
+            break;
+        };
+        if shutdown_rx.try_recv().is_ok() {
+            break;
+        }
+        if sent != Some(level) {
+            equipment.set_target_power(level).await?;
+            sent = Some(level);
+        }
+        if let Some(data) = equipment.read().await? {
+            samples.push(Sample {
+                // reads can wait for the next notification, so the time is taken after it arrived
+                time: start.elapsed().as_secs_f64(),
+                power: data.power as f64,
+                resistance: data.resistance,
+            });
+        }
+        print!("\r{time:5.1} / {:5.1} s :: level {level:<3}", steps.duration());
+        std::io::stdout().flush()?;
+    }
+    println!();
+    Ok(samples)
+}
+
+/// the levels and cadences of the power sweep, every level is ridden at every cadence, slowest first
+#[derive(Debug, Clone)]
+pub struct Sweep {
+    levels: Vec<i16>,
+    cadences: Vec<f64>,
+    /// seconds to hold every combination
+    hold: f64,
+}
+
+impl Sweep {
+    /// `count` levels spread evenly from `low` to `high`
+    pub fn new(low: i16, high: i16, count: usize, cadences: &[f64], hold: f64) -> Self {
+        let count = count.max(2);
+        let mut levels: Vec<i16> = (0..count)
+            .map(|i| low + ((high - low) as f64 * i as f64 / (count - 1) as f64).round() as i16)
+            .collect();
+        levels.dedup();
+        let mut cadences = cadences.to_vec();
+        cadences.sort_by(f64::total_cmp);
+        Sweep { levels, cadences, hold }
+    }
+
+    pub fn duration(&self) -> f64 {
+        (self.levels.len() * self.cadences.len()) as f64 * self.hold
+    }
+
+    /// the level and cadence to hold at `time`, and how long they've been held
+    fn at(&self, time: f64) -> Option<(i16, f64, f64)> {
+        let index = (time / self.hold) as usize;
+        let level = *self.levels.get(index / self.cadences.len().max(1))?;
+        let cadence = *self.cadences.get(index % self.cadences.len())?;
+        Some((level, cadence, time - index as f64 * self.hold))
+    }
+}
+
+/// ride the sweep, and collect the power at every level and cadence
+///
+/// stops early, with what was collected so far, when something arrives on `shutdown_rx`
+pub async fn sweep(equipment: &dyn Equipment, sweep: &Sweep, shutdown_rx: &Receiver<()>) -> anyhow::Result<Vec<Point>> {
+    let mut points = Vec::new();
+    let mut sent = None;
+    let start = Instant::now();
+    let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        let time = start.elapsed().as_secs_f64();
+        let Some((level, cadence, held)) = sweep.at(time) else {
+            break;
+        };
+        if shutdown_rx.try_recv().is_ok() {
+            break;
+        }
+        if sent != Some(level) {
+            equipment.set_target_power(level).await?;
+            sent = Some(level);
+        }
+        let Some(data) = equipment.read().await? else {
+            continue;
+        };
+        // a standing rider or a dropped reading says nothing about the level
+        if held >= SETTLE && data.cadence > 0. && data.power > 0 {
+            points.push(Point {
+                level: level as f64,
+                cadence: data.cadence as f64,
+                watts: data.power as f64,
+            });
+        }
+        print!(
+            "\r{time:5.0} / {:5.0} s :: level {level:<3} :: pedal at {cadence:.0} rpm (now {:03.0} rpm, {:03} W)",
+            sweep.duration(),
+            data.cadence,
+            data.power
+        );
+        std::io::stdout().flush()?;
+    }
+    println!();
+    Ok(points)
+}
+
+/// the lag between the commanded steps and the measured response, up to `max_lag` seconds
+///
+/// both the power and the resistance reading are tried, whichever follows the steps best wins
+pub fn estimate(steps: &Steps, samples: &[Sample], max_lag: f64) -> Option<Estimate> {
+    let end = samples.last()?.time;
+    let grid: Vec<f64> = (0..(end / RESOLUTION) as usize)
+        .map(|i| i as f64 * RESOLUTION)
+        .collect();
+    let commanded: Vec<f64> = grid
+        .iter()
+        .map(|&time| steps.level_at(time).unwrap_or_default() as f64)
+        .collect();
+    // readings are held until the next one comes in
+    let held = |reading: fn(&Sample) -> f64| -> Vec<f64> {
+        let mut next = 0;
+        grid.iter()
+            .map(|&time| {
+                while next + 1 < samples.len() && samples[next + 1].time <= time {
+                    next += 1;
+                }
+                reading(&samples[next])
+            })
+            .collect()
+    };
+    let power = lag(&commanded, &held(|sample| sample.power), max_lag);
+    let resistance = lag(&commanded, &held(|sample| sample.resistance), max_lag);
+    let (best, from_resistance) = match (power, resistance) {
+        (Some(power), Some(resistance)) if resistance.1 > power.1 => (resistance, true),
+        (Some(power), _) => (power, false),
+        (None, Some(resistance)) => (resistance, true),
+        (None, None) => return None,
+    };
+    (best.1 >= MIN_CORRELATION).then_some(Estimate {
+        latency: best.0,
+        correlation: best.1,
+        from_resistance,
+    })
+}
+
+/// the delay of `measured` behind `commanded` with the highest normalized cross-correlation, and that correlation
+fn lag(commanded: &[f64], measured: &[f64], max_lag: f64) -> Option<(f64, f64)> {
+    let max_shift = ((max_lag / RESOLUTION) as usize).min(commanded.len().saturating_sub(2));
+    (0..=max_shift)
+        .filter_map(|shift| {
+            let n = commanded.len() - shift;
+            correlation(&commanded[..n], &measured[shift..shift + n]).map(|r| (shift as f64 * RESOLUTION, r))
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// pearson correlation, or nothing if either side is flat
+fn correlation(a: &[f64], b: &[f64]) -> Option<f64> {
+    let n = a.len() as f64;
+    let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+    let (mut covariance, mut variance_a, mut variance_b) = (0., 0., 0.);
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a).powi(2);
+        variance_b += (y - mean_b).powi(2);
+    }
+    (variance_a > 0. && variance_b > 0.).then(|| covariance / (variance_a * variance_b).sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_delayed_response() {
+        let steps = Steps::new(2, 12, 10, 6.);
+        // power eases towards 10 W per level 1.3 s after every change, reported about 3 times a second,
+        // while the resistance reading is stuck
+        let mut power = 20.;
+        let samples: Vec<Sample> = (0..(steps.duration() * 3.) as usize)
+            .map(|i| {
+                let time = i as f64 / 3. + 0.01 * (i % 5) as f64;
+                let target = steps.level_at((time - 1.3).max(0.)).unwrap_or(2) as f64 * 10.;
+                power += (target - power) * 0.7;
+                Sample {
+                    time,
+                    power: power.round(),
+                    resistance: 0.,
+                }
+            })
+            .collect();
+
+        let estimate = estimate(&steps, &samples, 5.).unwrap();
+        assert!(!estimate.from_resistance);
+        assert!((estimate.latency - 1.5).abs() < 0.4, "{estimate:?}");
+        assert!(estimate.correlation > 0.8, "{estimate:?}");
+    }
+}
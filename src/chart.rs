@@ -0,0 +1,27 @@
+//! tiny terminal charts, for eyeballing curves without leaving the terminal
+
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// draw `values` (from 0.0 to 1.0) as a single line of block characters, `width` characters wide
+///
+/// every character shows the average of the values it covers
+pub fn sparkline(values: &[f64], width: usize) -> String {
+    if values.is_empty() || width == 0 {
+        return String::new();
+    }
+    let width = width.min(values.len());
+    (0..width)
+        .map(|column| {
+            let start = column * values.len() / width;
+            let end = ((column + 1) * values.len() / width).max(start + 1);
+            let average = values[start..end].iter().sum::<f64>() / (end - start) as f64;
+            let index = (average.clamp(0., 1.) * (BLOCKS.len() - 1) as f64).round() as usize;
+            BLOCKS[index]
+        })
+        .collect()
+}
+
+/// how wide charts should be, leaving room for a label
+pub fn width() -> usize {
+    crossterm::terminal::size().map_or(80, |(columns, _)| columns as usize).saturating_sub(10)
+}
@@ -1,15 +1,25 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::audio::{GainMode, Normalization, NormalizationScope, TempoDetection};
+use crate::power::ControlMode;
 
 /// audiosurf irl or something
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 pub struct Args {
-    #[arg(required = true, help = "Path to the directory containing FLAC files")]
-    pub path: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[arg(
+        required_unless_present = "preview_curve",
+        help = "Path to a directory of FLAC, Ogg, WAV or MKA files, or a single one of them"
+    )]
+    pub path: Option<PathBuf>,
 
     #[arg(
+        global = true,
         short,
         long,
         default_value_t = 1.,
@@ -18,12 +28,148 @@ pub struct Args {
     pub scale: f64,
 
     #[arg(
+        global = true,
         short,
         long,
-        default_value_t = String::from("lufs"),
-        help = "sound analyzer type"
+        help = "sound analyzer type (fft, lufs, band, flux, hpss, composite or spectral) [default: lufs]"
+    )]
+    pub analyzer: Option<String>,
+
+    #[arg(
+        global = true,
+        short = 'O',
+        long,
+        help = "Analyzer option as key=value, can be given multiple times",
+        long_help = crate::analysis::describe()
+    )]
+    pub analyzer_option: Vec<String>,
+
+    #[arg(
+        global = true,
+        long,
+        value_enum,
+        help = "How the analyzer output is stretched over the level range [default: fixed]"
+    )]
+    pub normalization: Option<Normalization>,
+
+    #[arg(
+        global = true,
+        long,
+        value_enum,
+        help = "Whether each track uses the full level range on its own, or tracks share one range [default: track]"
     )]
-    pub analyzer: String,
+    pub normalization_scope: Option<NormalizationScope>,
+
+    #[arg(
+        global = true,
+        long,
+        value_enum,
+        default_value_t = TempoDetection::Auto,
+        help = "When to detect the tempo of tracks for cadence scoring"
+    )]
+    pub tempo_detection: TempoDetection,
+
+    #[arg(
+        global = true,
+        long,
+        default_value_t = false,
+        action,
+        help = "Detect the sections of each song (intro, verse, chorus, ...) and hold the level steady within them"
+    )]
+    pub sections: bool,
+
+    #[arg(
+        global = true,
+        long,
+        help = "Post-processing applied to the level curve, as comma separated stages (e.g. median:1,envelope:0.2:2,hold:3)",
+        long_help = "Post-processing applied to the level curve, as comma separated stages run in order:\n\
+        \n  average:SECONDS           moving average over a window\
+        \n  envelope:ATTACK:RELEASE   rise within ATTACK seconds, fall within RELEASE seconds\
+        \n  median:SECONDS            median filter over a window, drops short spikes\
+        \n  hysteresis:DEADBAND       ignore changes smaller than DEADBAND (levels from 0.0 to 1.0)\
+        \n  hold:SECONDS              keep every new level for at least SECONDS"
+    )]
+    pub pipeline: Option<String>,
+
+    #[arg(
+        global = true,
+        long,
+        help = "How the level curve maps onto equipment levels: linear, gamma:GAMMA, exp:RATE, sigmoid:CONTRAST[:CENTER] or piecewise:VALUE=SHARE,... [default: linear]"
+    )]
+    pub curve: Option<String>,
+
+    #[arg(global = true, long, help = "Minimum level to set on the exercise equipment [default: 1]")]
+    pub min_level: Option<i16>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action,
+        help = "Print how the level curve maps onto equipment levels, and exit"
+    )]
+    pub preview_curve: bool,
+
+    #[arg(
+        long,
+        help = "Ride for watts instead of levels, as LOW:HIGH watts (e.g. 100:250) or percent of the FTP (e.g. 55%:105%). without ERG, this takes the power model from `music-rider calibrate power`"
+    )]
+    pub target_watts: Option<String>,
+
+    #[arg(
+        long,
+        value_enum,
+        help = "Whether to send levels, or target watts for the equipment to hold (ERG). none of the supported equipment holds watts yet, so ERG only works with --no-discovery [default: auto, ERG when the equipment holds watts, levels otherwise]"
+    )]
+    pub control_mode: Option<ControlMode>,
+
+    #[arg(
+        long,
+        help = "Bend the level curve so the album adds up to a workload, in kJ (e.g. 450kJ) or TSS (e.g. 80tss, needs --ftp). needs watts: --target-watts, ERG or a power model"
+    )]
+    pub workload: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action,
+        help = "Ease the levels off when the cadence collapses or the watts fall short, and push them up when the rider cruises"
+    )]
+    pub adaptive: bool,
+
+    #[arg(global = true, long, help = "Functional threshold power of the rider, in watts, time in zone is reported after the ride when it's known")]
+    pub ftp: Option<f64>,
+
+    #[arg(
+        global = true,
+        long,
+        default_value_t = false,
+        action,
+        help = "Ride the training zones of the music instead of its levels: quiet parts in Z2, choruses in Z4 and drops briefly in Z6 (needs --ftp)"
+    )]
+    pub zones: bool,
+
+    #[arg(
+        global = true,
+        long,
+        help = "How much the note density of a StepMania or osu! chart next to a track weighs into its levels, from 0 to 1 [default: 0.3]"
+    )]
+    pub beatmap_weight: Option<f64>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = GainMode::Off,
+        help = "Play tracks back at the loudness their ReplayGain tags ask for"
+    )]
+    pub replay_gain: GainMode,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action,
+        help = "Print the level curve of each track before and after post-processing"
+    )]
+    pub show_levels: bool,
 
     #[arg(
         long,
@@ -43,6 +189,7 @@ pub struct Args {
     pub debug: bool,
 
     #[arg(
+        global = true,
         short,
         long,
         default_value_t = 50,
@@ -50,6 +197,19 @@ pub struct Args {
     )]
     pub max_level: i16,
 
+    #[arg(
+        global = true,
+        long,
+        help = "How many levels per second the exercise equipment can change its resistance by, levels are ramped ahead of changes to keep up [default: unlimited]"
+    )]
+    pub ramp_rate: Option<f64>,
+
+    #[arg(global = true, long, help = "Minimum time between writes to the exercise equipment, in seconds [default: 1]")]
+    pub write_interval: Option<f64>,
+
+    #[arg(global = true, long, help = "Maximum number of writes to the exercise equipment per minute [default: unlimited]")]
+    pub max_writes: Option<usize>,
+
     #[arg(
         long,
         default_value_t = false,
@@ -59,6 +219,7 @@ pub struct Args {
     pub no_read: bool,
 
     #[arg(
+        global = true,
         short,
         long,
         default_value_t = String::from("28"),
@@ -66,6 +227,142 @@ pub struct Args {
     )]
     pub exercise_equipment_type: String,
 
-    #[arg(short, long, default_value_t = 20., help = "song offset (in ms)")]
-    pub offset: f32,
+    #[arg(
+        global = true,
+        long,
+        default_value_t = false,
+        action,
+        help = "Always rescan tracks instead of loading previously computed ride profiles"
+    )]
+    pub no_cache: bool,
+
+    #[arg(
+        global = true,
+        short,
+        long,
+        help = "Path to the config file [default: ~/.config/music-rider/config.toml]"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        help = "song offset (in ms), `music-rider calibrate` can measure it [default: the calibrated offset, or 20]"
+    )]
+    pub offset: Option<f32>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Scan tracks and write their features and levels to files, along with a plot to compare them
+    Analyze(AnalyzeArgs),
+    /// Show the BPM, difficulty and average level each track would be tagged with, and write them with --write
+    Tag(TagArgs),
+    /// Measure how late the exercise equipment responds, or how much power its levels take, and save it in the config
+    Calibrate(CalibrateArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AnalyzeArgs {
+    #[arg(help = "Path to an audio file, or a directory containing them")]
+    pub path: PathBuf,
+
+    #[arg(short = 'D', long, default_value = ".", help = "Directory to write the results to")]
+    pub output: PathBuf,
+
+    #[arg(
+        short,
+        long,
+        value_enum,
+        default_value_t = ExportFormat::Csv,
+        help = "Format of the features and levels of each track"
+    )]
+    pub format: ExportFormat,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = PlotFormat::Svg,
+        help = "Format of the plot of the levels of every track, side by side"
+    )]
+    pub plot: PlotFormat,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TagArgs {
+    #[arg(help = "Path to an audio file, or a directory containing them")]
+    pub path: PathBuf,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action,
+        help = "Write the tags, instead of only showing what would change"
+    )]
+    pub write: bool,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CalibrateArgs {
+    #[arg(value_enum, default_value_t = Calibration::Latency, help = "What to measure")]
+    pub kind: Calibration,
+
+    #[arg(long, help = "Level of the easy steps, or the lowest level of the power sweep [default: the minimum level]")]
+    pub low: Option<i16>,
+
+    #[arg(
+        long,
+        help = "Level of the hard steps, or the highest level of the power sweep [default: halfway up to the maximum level for latency, the maximum level for power]"
+    )]
+    pub high: Option<i16>,
+
+    #[arg(long, default_value_t = 12, help = "Number of steps to ride, or of levels to sweep")]
+    pub steps: usize,
+
+    #[arg(long, default_value_t = 8., help = "Average length of a step, in seconds")]
+    pub step_length: f64,
+
+    #[arg(long, default_value_t = 5., help = "Longest latency to look for, in seconds")]
+    pub max_latency: f64,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "60,75,90",
+        help = "Cadences to ride every level of the power sweep at, in rpm"
+    )]
+    pub cadences: Vec<f64>,
+
+    #[arg(long, default_value_t = 12., help = "Seconds to hold every level and cadence of the power sweep")]
+    pub hold: f64,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        action,
+        help = "Only print the results, instead of saving them in the config"
+    )]
+    pub dry_run: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Calibration {
+    /// ride a few level steps to measure how late the equipment responds, saved as its offset
+    Latency,
+    /// ride every level at a few cadences to fit a level × cadence → watts model, for `--target-watts`
+    Power,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlotFormat {
+    Svg,
+    Png,
+    /// don't plot
+    None,
 }
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use serde::Deserialize;
+
+/// settings read from `config.toml`, for everything that's tedious to type out on every run
+///
+/// command line arguments win over the config file
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub analysis: AnalysisConfig,
+    pub levels: LevelsConfig,
+    pub equipment: EquipmentConfig,
+    pub rider: RiderConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisConfig {
+    pub analyzer: Option<String>,
+    /// analyzer options, like `--analyzer-option key=value`
+    pub options: BTreeMap<String, toml::Value>,
+}
+
+impl AnalysisConfig {
+    /// the options as `key=value` pairs
+    pub fn option_pairs(&self) -> Vec<String> {
+        self.options
+            .iter()
+            .map(|(key, value)| match value {
+                toml::Value::String(value) => format!("{key}={value}"),
+                value => format!("{key}={value}"),
+            })
+            .collect()
+    }
+}
+
+/// how analyzer scores become levels
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LevelsConfig {
+    /// like `--normalization`
+    pub normalization: Option<String>,
+    /// like `--normalization-scope`
+    pub normalization_scope: Option<String>,
+    /// post-processing stages, like `--pipeline`
+    pub pipeline: Option<String>,
+    /// the score to level curve, like `--curve`
+    pub curve: Option<String>,
+    /// like `--min-level`
+    pub min_level: Option<i16>,
+    /// like `--beatmap-weight`
+    pub beatmap_weight: Option<f64>,
+    /// like `--target-watts`
+    pub target_watts: Option<String>,
+}
+
+/// what the exercise equipment can keep up with
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EquipmentConfig {
+    /// like `--ramp-rate`
+    pub ramp_rate: Option<f64>,
+    /// like `--write-interval`
+    pub write_interval: Option<f64>,
+    /// like `--max-writes`
+    pub max_writes: Option<usize>,
+    /// like `--offset`, per `--exercise-equipment-type`, as measured by `music-rider calibrate`
+    pub offsets: BTreeMap<String, f32>,
+    /// like `--control-mode`
+    pub control_mode: Option<String>,
+    /// level × cadence → watts surfaces per `--exercise-equipment-type`, as fitted by `music-rider calibrate power`
+    pub power_models: BTreeMap<String, Vec<f64>>,
+}
+
+/// who's riding
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiderConfig {
+    /// like `--ftp`
+    pub ftp: Option<f64>,
+    /// like `--zones`
+    pub zones: Option<bool>,
+    /// like `--adaptive`
+    pub adaptive: Option<bool>,
+}
+
+/// where the config lives unless told otherwise, e.g. `~/.config/music-rider/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+    Some(dirs::config_dir()?.join("music-rider").join("config.toml"))
+}
+
+impl Config {
+    /// load the config from `path`, or from the default location if it exists
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config {}", path.display()))
+    }
+}
+
+/// remember the offset of a kind of equipment in the config at `path` (or the default location)
+///
+/// returns where the config was written
+pub fn save_offset(path: Option<&Path>, equipment: &str, offset: f32) -> anyhow::Result<PathBuf> {
+    save_equipment_value(path, "offsets", equipment, toml_edit::value(offset.round() as f64))
+}
+
+/// remember the power model of a kind of equipment in the config at `path` (or the default location)
+///
+/// returns where the config was written
+pub fn save_power_model(path: Option<&Path>, equipment: &str, coefficients: &[f64]) -> anyhow::Result<PathBuf> {
+    let array: toml_edit::Array = coefficients.iter().copied().collect();
+    save_equipment_value(path, "power_models", equipment, toml_edit::value(array))
+}
+
+/// set `equipment.<table>.<equipment>` in the config, leaving everything else in it, comments included, as it was
+fn save_equipment_value(
+    path: Option<&Path>,
+    table: &str,
+    equipment: &str,
+    value: toml_edit::Item,
+) -> anyhow::Result<PathBuf> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => default_path().context("no config directory to save into")?,
+    };
+    let text = match std::fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("failed to read config {}", path.display())),
+    };
+    let mut document: toml_edit::DocumentMut =
+        text.parse().with_context(|| format!("invalid config {}", path.display()))?;
+    let equipment_table = document
+        .entry("equipment")
+        .or_insert_with(toml_edit::table)
+        .as_table_mut()
+        .context("`equipment` in the config isn't a table")?;
+    let values = equipment_table
+        .entry(table)
+        .or_insert_with(toml_edit::table)
+        .as_table_like_mut()
+        .with_context(|| format!("`equipment.{table}` in the config isn't a table"))?;
+    values.insert(equipment, value);
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, document.to_string())
+        .with_context(|| format!("failed to write config {}", path.display()))?;
+    Ok(path)
+}
@@ -0,0 +1,134 @@
+//! `music-rider analyze`: the features and levels of every track, as files and a plot
+
+use std::fmt::Write as _;
+
+use crate::audio::{Audio, EXTENSIONS};
+use crate::cli::{AnalyzeArgs, ExportFormat, PlotFormat};
+use crate::planner::{Limits, Plan};
+use crate::plot::{self, Panel, Series};
+use crate::profile::RideProfile;
+use crate::transfer::Transfer;
+
+/// a row per profile frame: the time, the level curve, the level set on the equipment, and every feature
+pub fn csv(profile: &RideProfile, plan: &Plan) -> String {
+    let mut csv = String::from("time,level,equipment_level");
+    for curve in &profile.features {
+        csv.push(',');
+        csv.push_str(&curve.name);
+    }
+    csv.push('\n');
+    for (frame, level) in profile.levels.iter().enumerate() {
+        let time = frame as f64 / profile.frame_rate;
+        let equipment_level = plan.level_at(time).map(|level| level.to_string()).unwrap_or_default();
+        let _ = write!(csv, "{time:.3},{level:.4},{equipment_level}");
+        for curve in &profile.features {
+            csv.push(',');
+            if let Some(value) = curve.values.get(frame) {
+                let _ = write!(csv, "{value:.4}");
+            }
+        }
+        csv.push('\n');
+    }
+    csv
+}
+
+/// the whole profile, along with the level set on the equipment for every frame
+pub fn json(profile: &RideProfile, plan: &Plan) -> anyhow::Result<String> {
+    let equipment_levels: Vec<Option<i16>> = (0..profile.levels.len())
+        .map(|frame| plan.level_at(frame as f64 / profile.frame_rate))
+        .collect();
+    Ok(serde_json::to_string_pretty(&serde_json::json!({
+        "profile": profile,
+        "equipment_levels": equipment_levels,
+    }))?)
+}
+
+/// the level curves of a track, with the equipment level scaled to the same range
+fn panel(title: String, profile: &RideProfile, plan: &Plan, transfer: &Transfer) -> Panel {
+    let curve = |values: &[f64]| -> Vec<(f64, f64)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(frame, &value)| (frame as f64 / profile.frame_rate, value))
+            .collect()
+    };
+    let mut series = Vec::new();
+    if let Some(raw) = profile.feature("raw_level") {
+        series.push(Series {
+            name: "raw level",
+            color: [160, 160, 160],
+            points: curve(&raw.values),
+        });
+    }
+    series.push(Series {
+        name: "level",
+        color: [31, 119, 180],
+        points: curve(&profile.levels),
+    });
+    // steps, as the equipment holds every level until the next write
+    let range = (transfer.max - transfer.min).max(1) as f64;
+    let mut equipment = Vec::new();
+    for write in &plan.writes {
+        let value = (write.level - transfer.min) as f64 / range;
+        if let Some(&(_, previous)) = equipment.last() {
+            equipment.push((write.time, previous));
+        }
+        equipment.push((write.time, value));
+    }
+    if let Some(&(_, last)) = equipment.last() {
+        equipment.push((profile.track.duration, last));
+    }
+    series.push(Series {
+        name: "equipment",
+        color: [255, 127, 14],
+        points: equipment,
+    });
+    Panel {
+        title,
+        duration: profile.track.duration,
+        series,
+    }
+}
+
+pub fn analyze(audio: &Audio, args: &AnalyzeArgs, transfer: &Transfer, limits: &Limits) -> anyhow::Result<()> {
+    if audio.tracks().is_empty() {
+        anyhow::bail!(
+            "no audio files ({}) found at {}",
+            EXTENSIONS.join(", "),
+            args.path.display()
+        );
+    }
+    std::fs::create_dir_all(&args.output)?;
+    let mut panels = Vec::new();
+    for (index, track) in audio.tracks().iter().enumerate() {
+        let profile = audio.ride_profile(index)?;
+        let plan = Plan::new(&profile, transfer, limits);
+        // the tracks all come from one directory, so their file names (unlike their stems, with `song.flac`
+        // next to `song.ogg`) can't collide
+        let name = track.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let (contents, extension) = match args.format {
+            ExportFormat::Csv => (csv(&profile, &plan), "csv"),
+            ExportFormat::Json => (json(&profile, &plan)?, "json"),
+        };
+        let path = args.output.join(format!("{name}.{extension}"));
+        std::fs::write(&path, contents)?;
+        println!("wrote {}", path.display());
+        panels.push(panel(name, &profile, &plan, transfer));
+    }
+
+    let path = match args.plot {
+        PlotFormat::Svg => {
+            let path = args.output.join("levels.svg");
+            std::fs::write(&path, plot::svg(&panels))?;
+            path
+        }
+        PlotFormat::Png => {
+            let path = args.output.join("levels.png");
+            plot::png(&panels, &path)?;
+            path
+        }
+        PlotFormat::None => return Ok(()),
+    };
+    println!("wrote {}", path.display());
+    Ok(())
+}
@@ -1,21 +1,272 @@
-use clap::Parser as _;
+use clap::{Parser as _, ValueEnum};
 use crossterm::style::Stylize;
 use crossterm::{ExecutableCommand, QueueableCommand, cursor, terminal};
 use kondis::{EquipmentType, equipment_type_to_equipment};
 use std::io::{Stdout, Write, stdout};
-use std::sync::mpsc::channel;
-use tokio::time::Instant;
+use std::sync::mpsc::{Receiver, TryRecvError, channel};
+use std::time::{Duration, Instant};
 
+mod adaptive;
 mod analysis;
 mod audio;
+mod beatmap;
+mod calibration;
+mod chart;
 mod cli;
+mod config;
+mod export;
+mod pipeline;
+mod planner;
+mod plot;
+mod power;
+mod profile;
+mod ride_chart;
+mod tags;
+mod transfer;
+mod workload;
+mod zones;
+
+/// how often the equipment loop samples the playback clock
+const TICK: Duration = Duration::from_millis(250);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args = cli::Args::parse();
+    let config = config::Config::load(args.config.as_deref())?;
+    let analyzer = args
+        .analyzer
+        .as_deref()
+        .or(config.analysis.analyzer.as_deref())
+        .unwrap_or("lufs");
+    // options from the command line come last, so they win over the config file
+    let mut option_pairs = config.analysis.option_pairs();
+    option_pairs.extend(args.analyzer_option.iter().cloned());
+    let normalization = match (args.normalization, &config.levels.normalization) {
+        (Some(normalization), _) => normalization,
+        (None, Some(name)) => audio::Normalization::from_str(name, true)
+            .map_err(|e| anyhow::anyhow!("invalid normalization in config: {e}"))?,
+        (None, None) => audio::Normalization::Fixed,
+    };
+    let normalization_scope = match (args.normalization_scope, &config.levels.normalization_scope) {
+        (Some(scope), _) => scope,
+        (None, Some(name)) => audio::NormalizationScope::from_str(name, true)
+            .map_err(|e| anyhow::anyhow!("invalid normalization scope in config: {e}"))?,
+        (None, None) => audio::NormalizationScope::Track,
+    };
+    let settings = audio::ScanSettings {
+        analyzer: analyzer.parse()?,
+        options: analysis::AnalyzerOptions::parse(&option_pairs)?,
+        scale: args.scale,
+        normalization,
+        tempo_detection: args.tempo_detection,
+        sections: args.sections,
+    };
+    let pipeline: pipeline::Pipeline = args
+        .pipeline
+        .as_deref()
+        .or(config.levels.pipeline.as_deref())
+        .unwrap_or_default()
+        .parse()?;
+    let mut transfer = transfer::Transfer {
+        curve: args
+            .curve
+            .as_deref()
+            .or(config.levels.curve.as_deref())
+            .unwrap_or("linear")
+            .parse()?,
+        min: args.min_level.or(config.levels.min_level).unwrap_or(1),
+        max: args.max_level,
+    };
+    if transfer.min < 1 || transfer.min > transfer.max {
+        anyhow::bail!("the minimum level has to be between 1 and the maximum level ({})", transfer.max);
+    }
+    if args.preview_curve {
+        print!("{}", transfer.preview());
+        return Ok(());
+    }
+    let beatmap_weight = args.beatmap_weight.or(config.levels.beatmap_weight).unwrap_or(0.3);
+    if !(0. ..=1.).contains(&beatmap_weight) {
+        anyhow::bail!("the beatmap weight has to be between 0 and 1, got {beatmap_weight}");
+    }
+    let limits = planner::Limits {
+        ramp_rate: args.ramp_rate.or(config.equipment.ramp_rate),
+        write_interval: args.write_interval.or(config.equipment.write_interval).unwrap_or(1.),
+        max_writes: args.max_writes.or(config.equipment.max_writes),
+    };
+    let normalize_album = normalization_scope == audio::NormalizationScope::Album
+        && normalization != audio::Normalization::Fixed;
 
-    // channel for audio samples
-    let (tx, rx) = channel();
+    let ftp = args.ftp.or(config.rider.ftp);
+    let zone_ftp = match (args.zones || config.rider.zones.unwrap_or(false), ftp) {
+        (true, Some(ftp)) => Some(ftp),
+        (true, None) => anyhow::bail!("riding by training zones needs the FTP of the rider, set --ftp"),
+        (false, _) => None,
+    };
+    let scan_path = match &args.command {
+        Some(cli::Command::Calibrate(calibration)) => {
+            return calibrate(&args, calibration, transfer.min).await;
+        }
+        Some(cli::Command::Analyze(analyze)) => Some(&analyze.path),
+        Some(cli::Command::Tag(tag)) => Some(&tag.path),
+        None => None,
+    };
+    if let Some(path) = scan_path {
+        let mut audio = audio::Audio::new(
+            path.clone(),
+            settings,
+            !args.no_cache,
+            pipeline,
+            false,
+            beatmap_weight,
+            audio::PlaybackClock::new(),
+        );
+        if let Some(ftp) = zone_ftp {
+            audio.set_zones(ftp);
+        }
+        if normalize_album {
+            audio.normalize_album()?;
+        }
+        return match &args.command {
+            Some(cli::Command::Analyze(analyze)) => export::analyze(&audio, analyze, &transfer, &limits),
+            Some(cli::Command::Tag(tag)) => tags::run(&audio, tag.write),
+            _ => unreachable!("only analyze and tag scan tracks"),
+        };
+    }
+
+    let requested = match (args.control_mode, &config.equipment.control_mode) {
+        (Some(mode), _) => mode,
+        (None, Some(name)) => power::ControlMode::from_str(name, true)
+            .map_err(|e| anyhow::anyhow!("invalid control mode in config: {e}"))?,
+        (None, None) => power::ControlMode::Auto,
+    };
+    let control_mode = match requested {
+        power::ControlMode::Auto if holds_watts(&equipment_type(&args.exercise_equipment_type)) => power::ControlMode::Erg,
+        power::ControlMode::Auto => power::ControlMode::Level,
+        mode => mode,
+    };
+    // set_target_power is a level setter on equipment that takes levels, so watts can't go through it
+    let erg = control_mode == power::ControlMode::Erg;
+    if erg && !args.no_discovery && !holds_watts(&equipment_type(&args.exercise_equipment_type)) {
+        anyhow::bail!(
+            "equipment type {} takes resistance levels, not watts, so it can't ride in ERG mode: ride --target-watts with a power model from `music-rider calibrate power` instead",
+            args.exercise_equipment_type
+        );
+    }
+    let range = args
+        .target_watts
+        .as_deref()
+        .or(config.levels.target_watts.as_deref())
+        .map(|range| power::WattsRange::parse(range, ftp))
+        .transpose()?;
+    let control = if range.is_some() || zone_ftp.is_some() {
+        let model = if erg {
+            None
+        } else {
+            if args.no_read {
+                anyhow::bail!("riding for watts in level mode needs the cadence, so it doesn't work with --no-read");
+            }
+            let coefficients = config
+                .equipment
+                .power_models
+                .get(&args.exercise_equipment_type)
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "no power model for equipment type {}, run `music-rider calibrate power` first, or ride in ERG mode",
+                        args.exercise_equipment_type
+                    )
+                })?;
+            Some(coefficients.as_slice().try_into()?)
+        };
+        power::Control::Watts {
+            range,
+            min: transfer.min,
+            max: transfer.max,
+            model,
+        }
+    } else if requested == power::ControlMode::Erg {
+        // auto only rides ERG when there are watts to ride, and sends levels otherwise
+        anyhow::bail!("ERG mode needs the watts to ride between, set --target-watts or --zones")
+    } else {
+        power::Control::Level
+    };
+    // the equipment refuses anything above its maximum, which is in watts for equipment that holds them
+    let equipment_max = match &control {
+        power::Control::Watts { range, model: None, .. } => {
+            let range_max = range.map_or(0., |range| range.high);
+            let zone_max = zone_ftp.map_or(0., |ftp| zones::Zone::Neuromuscular.target(ftp));
+            range_max.max(zone_max).ceil() as i16
+        }
+        _ => args.max_level,
+    };
+    let path = args.path.expect("clap requires a path unless previewing the curve");
+    let workload = args.workload.as_deref().map(str::parse::<workload::Target>).transpose()?;
+    let mut predicted = None;
+    if let Some(target) = workload {
+        if zone_ftp.is_some() {
+            anyhow::bail!("the training zones already decide the watts from the FTP, so there's no curve to fit to a workload");
+        }
+        if matches!(target, workload::Target::Tss(_)) && ftp.is_none() {
+            anyhow::bail!("a workload in TSS needs the FTP of the rider, set --ftp");
+        }
+        // equipment that takes levels is predicted at a steady cadence
+        let model: Option<power::PowerModel> = match (&control, config.equipment.power_models.get(&args.exercise_equipment_type)) {
+            (power::Control::Level, Some(coefficients)) => Some(coefficients.as_slice().try_into()?),
+            _ => None,
+        };
+        let watts = |level: i16| match &control {
+            power::Control::Watts { .. } => control.command(level, Some(workload::CADENCE), None).watts,
+            power::Control::Level => model.as_ref().map(|model| model.watts(level as f64, workload::CADENCE)),
+        };
+        if watts(transfer.max).is_none() {
+            anyhow::bail!(
+                "predicting the workload needs the watts of every level: set --target-watts, ride in ERG mode, or run `music-rider calibrate power`"
+            );
+        }
+        let mut audio = audio::Audio::new(
+            path.clone(),
+            settings.clone(),
+            !args.no_cache,
+            pipeline.clone(),
+            false,
+            beatmap_weight,
+            audio::PlaybackClock::new(),
+        );
+        if normalize_album {
+            audio.normalize_album()?;
+        }
+        let profiles = (0..audio.album_length)
+            .map(|track| audio.ride_profile(track))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let before = workload::predict(&profiles, &transfer, &limits, &watts);
+        let (curve, prediction) = workload::fit(&profiles, &transfer, &limits, &watts, target, ftp);
+        println!(
+            "fitted {} tracks to {target} with the {curve} curve (the {} curve would have been {:.0} kJ)",
+            profiles.len(),
+            transfer.curve,
+            before.kilojoules()
+        );
+        println!("predicted: {}", prediction.report(ftp));
+        let reached = prediction.of(target, ftp);
+        if (reached - target.value()).abs() > target.value() * 0.05 {
+            println!("that's as close as the curve gets, widen the levels or --target-watts to reach {target}");
+        }
+        transfer.curve = curve;
+        predicted = Some(prediction);
+    }
+    let mut adaptive = if args.adaptive || config.rider.adaptive.unwrap_or(false) {
+        if args.no_read || args.no_discovery {
+            anyhow::bail!("adapting the difficulty needs readings from the equipment, so it doesn't work with --no-read or --no-discovery");
+        }
+        Some(adaptive::Adaptive::default())
+    } else {
+        None
+    };
+    let (min_level, max_level) = (transfer.min, transfer.max);
+    let write_interval = Duration::from_secs_f64(limits.write_interval);
+    let mut planner = planner::Planner::new(transfer, limits);
+
+    // the music player publishes where it is, and what the current track looks like
+    let clock = audio::PlaybackClock::new();
 
     // the bike decides when to start
     let (play_tx, play_rx) = channel();
@@ -37,30 +288,57 @@ async fn main() -> anyhow::Result<()> {
         shutdown_tx3.send(()).unwrap();
     });
 
-    // spawn a task to play the audio files and send samples to the main thread
+    // magic number: 0.4 = loudness momentary window.
+    // dividing it by 2 gets us the loudness halfway through the window
+    // we add this value to the offset which is the number of seconds to offset in addition
+    // (to account for latency applying this to the bike)
+    let offset = args
+        .offset
+        .or_else(|| config.equipment.offsets.get(&args.exercise_equipment_type).copied())
+        .unwrap_or(20.);
+    let lead = 0.4 / 2. + offset as f64 / 1000.;
+
+    // spawn a task to play the audio files and move the playhead along
+    let player_clock = clock.clone();
     tokio::spawn(async move {
-        let mut audio = audio::Audio::new(args.path, args.scale, args.offset);
-        let play = play_rx.recv().is_ok();
-        for _ in 0..audio.album_length {
-            if play {
-                audio
-                    .play_track(tx.clone(), &mut shutdown_rx, args.analyzer.clone())
-                    .unwrap();
-                if audio.next_track().is_none() {
-                    println!("No more tracks to play.");
-                    stop_tx.send(()).unwrap();
-                    break;
+        let mut audio = audio::Audio::new(
+            path,
+            settings,
+            !args.no_cache,
+            pipeline,
+            args.show_levels,
+            beatmap_weight,
+            player_clock,
+        );
+        audio.set_replay_gain(args.replay_gain);
+        if let Some(ftp) = zone_ftp {
+            audio.set_zones(ftp);
+        }
+        // the player can't leave its thread, so a track that fails here stops the ride instead of the program
+        let played = (|| -> anyhow::Result<()> {
+            if normalize_album {
+                audio.normalize_album()?;
+            }
+            let play = play_rx.recv().is_ok();
+            for _ in 0..audio.album_length {
+                if play {
+                    audio.play_track(&mut shutdown_rx)?;
+                    if audio.next_track().is_none() {
+                        println!("No more tracks to play.");
+                        break;
+                    }
                 }
             }
+            Ok(())
+        })();
+        if let Err(e) = played {
+            println!("\nthe player stopped: {e:#}");
         }
         audio.flush();
+        stop_tx.send(()).ok();
     });
 
-    let equipment_type = match args.exercise_equipment_type.as_str() {
-        "28" => EquipmentType::Iconsole0028Bike,
-        "debug" => EquipmentType::DebugBike,
-        _ => EquipmentType::NonBluetoothDevice,
-    };
+    let equipment_type = equipment_type(&args.exercise_equipment_type);
 
     // "pretty" printing
     let mut stdout = stdout();
@@ -92,14 +370,27 @@ async fn main() -> anyhow::Result<()> {
     // no discovery means we just print the levels to stdout
     if args.no_discovery {
         // enable playback
-        play_tx.send(true).unwrap();
+        // the player is gone already if it failed, which the loop below finds out from stop_rx
+        play_tx.send(true).ok();
 
-        // receive samples, analyze them, and print the resulting levels
-        while let Ok((_bpm, value)) = rx.recv() {
-            let level = freq_score_to_level(args.max_level, value);
+        // sample the playback clock, and print the resulting levels
+        let mut ticker = tokio::time::interval(TICK);
+        loop {
+            ticker.tick().await;
+            if playback_over(&shutdown_rx2, &stop_rx) {
+                break;
+            }
+            let Some((profile, playhead)) = clock.lookahead(lead) else {
+                continue;
+            };
+            let level = planner.level_at(&profile, playhead.position);
+            let watts = control.command(level, None, zones::target_watts(&profile, playhead.position)).watts;
             let level_state = format!(
-                "level {:<width$}",
+                "track {:02} :: {} :: level {:<width$}{}",
+                playhead.track + 1,
+                tempo_state(&profile, playhead.position),
                 "#".repeat((level / 2) as usize),
+                watts.map(|watts| format!(" ({watts:03.0} W)")).unwrap_or_default(),
                 width = args.max_level as usize
             );
             print_state(&mut stdout, level_state, 0.);
@@ -108,7 +399,7 @@ async fn main() -> anyhow::Result<()> {
     } else {
         // connect to the bike
         let equipment =
-            equipment_type_to_equipment(equipment_type, args.max_level, &mut shutdown_rx3).await;
+            equipment_type_to_equipment(equipment_type, equipment_max, &mut shutdown_rx3).await;
 
         if equipment.is_none() {
             return Ok(());
@@ -120,36 +411,77 @@ async fn main() -> anyhow::Result<()> {
         }
 
         // enable playback
-        play_tx.send(true).unwrap();
-        let time = Instant::now();
-        let mut prev_sent = 0;
+        // the player is gone already if it failed, which the loop below finds out from stop_rx
+        play_tx.send(true).ok();
+        let mut prev_sent = None;
+        let mut last_write = Instant::now();
+        let mut cadence = None;
         let mut final_score = 0.;
+        let mut time_in_zone = zones::TimeInZone::default();
+        let mut ridden = workload::Progress::default();
+        let mut last_read = Instant::now();
 
-        // receive samples, analyze them, and set the equipment level accordingly (and also print the levels lol)
-        while let Ok((bpm, value)) = rx.recv() {
-            if shutdown_rx2.try_recv().is_ok() {
+        // sample the playback clock, and set the equipment level accordingly (and also print the levels lol)
+        let mut ticker = tokio::time::interval(TICK);
+        loop {
+            ticker.tick().await;
+            if playback_over(&shutdown_rx2, &stop_rx) {
                 break;
             }
-            if stop_rx.try_recv().is_ok() {
-                break; // stop playback if the stop channel is closed
+            let Some((profile, playhead)) = clock.lookahead(lead) else {
+                continue;
+            };
+            let value = profile.level_at(playhead.position).unwrap_or_default();
+            let bpm = profile.bpm_at(playhead.position);
+            // the planner already spaces out writes, so only changes get sent
+            let mut planned = planner.level_at(&profile, playhead.position);
+            let mut zone_watts = zones::target_watts(&profile, playhead.position);
+            if let Some(adaptive) = &adaptive {
+                planned = adaptive.level(planned, min_level, max_level);
+                zone_watts = zone_watts.map(|watts| watts * adaptive.factor());
             }
-            let elapsed = time.elapsed().as_secs();
-            let level = freq_score_to_level(args.max_level, value);
-            if prev_sent < elapsed {
-                equipment.set_target_power(level).await?;
-                prev_sent = elapsed;
+            let command = control.command(planned, cadence, zone_watts);
+            let level = command.level;
+            // following the cadence changes the level on top of the plan, so those writes get spaced out here
+            let spaced = command.watts.is_none() || control.is_erg() || last_write.elapsed() >= write_interval;
+            if prev_sent != Some(command.value) && spaced {
+                equipment.set_target_power(command.value).await?;
+                prev_sent = Some(command.value);
+                last_write = Instant::now();
             }
             let level_state = format!(
-                "value {value:.2} :: level {:<02} {:<width$}",
+                "track {:02} :: {} :: value {value:.2} :: level {:<02}{} {:<width$}",
+                playhead.track + 1,
+                tempo_state(&profile, playhead.position),
                 level,
+                command.watts.map(|watts| format!(" ({watts:03.0} W)")).unwrap_or_default(),
                 "#".repeat((level / 2) as usize),
                 width = args.max_level as usize
             );
             if args.no_read {
                 print_state(&mut stdout, level_state, 0.);
             } else if let Some(data) = equipment.read().await? {
+                cadence = (data.cadence > 0.).then_some(data.cadence as f64);
+                let elapsed = last_read.elapsed().as_secs_f64();
+                if let Some(ftp) = ftp {
+                    time_in_zone.add(data.power as f64, ftp, elapsed);
+                }
+                ridden.push(data.power as f64, elapsed);
+                if let Some(adaptive) = &mut adaptive {
+                    // the equipment holds the watts itself in ERG mode, so only the cadence tells
+                    let expected = command.watts.filter(|_| !control.is_erg());
+                    adaptive.update(data.cadence as f64, data.power as f64, expected, elapsed);
+                }
+                last_read = Instant::now();
+                let progress = workload
+                    .map(|target| format!(" :: {:.0}/{target}", ridden.of(target, ftp)))
+                    .unwrap_or_default();
+                let factor = adaptive
+                    .as_ref()
+                    .map(|adaptive| format!(" :: ×{:.2}", adaptive.factor()))
+                    .unwrap_or_default();
                 let state = format!(
-                    "{:03} rpm :: {:03} W :: {:.2} km/h :: {:03} s",
+                    "{:03} rpm :: {:03} W :: {:.2} km/h :: {:03} s{progress}{factor}",
                     data.cadence, data.power, data.speed, data.time
                 );
                 let bpm_score = get_score(data.cadence, bpm);
@@ -158,8 +490,21 @@ async fn main() -> anyhow::Result<()> {
             }
         }
         stdout.execute(cursor::Show).unwrap();
+        if time_in_zone.total() > 0. {
+            println!("\ntime in zone");
+            print!("{time_in_zone}");
+        }
+        if let Some(predicted) = &predicted
+            && !args.no_read
+        {
+            println!("\nworkload: {} (predicted {})", ridden.report(ftp), predicted.report(ftp));
+        }
+        if let Some(adaptive) = &adaptive {
+            println!("\nadaptive difficulty");
+            print!("{adaptive}");
+        }
 
-        // cleanly disconnect the equipment once the songs are done playing and `rx` is closed
+        // cleanly disconnect the equipment once the songs are done playing
         // todo: disconnect the equipment, and flush the audio output on SIGINT or SIGTERM
         equipment.disconnect().await?;
     }
@@ -167,6 +512,210 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// whether the equipment holds target watts itself (ERG), instead of taking resistance levels
+///
+/// kondis can't tell us, and none of the equipment types it supports so far does: their target power command sets
+/// the resistance level
+fn holds_watts(equipment_type: &EquipmentType) -> bool {
+    match equipment_type {
+        EquipmentType::Iconsole0028Bike | EquipmentType::DebugBike | EquipmentType::NonBluetoothDevice => false,
+    }
+}
+
+fn equipment_type(name: &str) -> EquipmentType {
+    match name {
+        "28" => EquipmentType::Iconsole0028Bike,
+        "debug" => EquipmentType::DebugBike,
+        _ => EquipmentType::NonBluetoothDevice,
+    }
+}
+
+/// measure the latency or the power of the equipment, and save it in the config
+async fn calibrate(args: &cli::Args, calibrate: &cli::CalibrateArgs, min_level: i16) -> anyhow::Result<()> {
+    let low = calibrate.low.unwrap_or(min_level);
+    let high = calibrate.high.unwrap_or(match calibrate.kind {
+        cli::Calibration::Latency => (min_level + args.max_level) / 2,
+        cli::Calibration::Power => args.max_level,
+    });
+    if low < 1 || low >= high || high > args.max_level {
+        anyhow::bail!(
+            "the calibration levels have to go up, between 1 and the maximum level ({}), got {low} and {high}",
+            args.max_level
+        );
+    }
+
+    let (shutdown_tx, mut shutdown_rx) = channel();
+    tokio::spawn(async move {
+        tokio::signal::ctrl_c().await.unwrap();
+        let _ = shutdown_tx.send(());
+    });
+    let equipment = equipment_type_to_equipment(
+        equipment_type(&args.exercise_equipment_type),
+        args.max_level,
+        &mut shutdown_rx,
+    )
+    .await;
+    let Some(mut equipment) = equipment else {
+        return Ok(());
+    };
+    if !equipment.connect().await? {
+        return Ok(());
+    }
+    let result = match calibrate.kind {
+        cli::Calibration::Latency => calibrate_latency(args, calibrate, equipment.as_ref(), low, high, &shutdown_rx).await,
+        cli::Calibration::Power => calibrate_power(args, calibrate, equipment.as_ref(), low, high, &shutdown_rx).await,
+    };
+    equipment.disconnect().await?;
+    result
+}
+
+/// ride level steps, and save how late the equipment follows them as its offset
+async fn calibrate_latency(
+    args: &cli::Args,
+    calibrate: &cli::CalibrateArgs,
+    equipment: &dyn kondis::Equipment,
+    low: i16,
+    high: i16,
+    shutdown_rx: &Receiver<()>,
+) -> anyhow::Result<()> {
+    let steps = calibration::Steps::new(low, high, calibrate.steps, calibrate.step_length);
+    println!(
+        "pedal at a steady cadence, the level switches between {low} and {high} for the next {:.0} seconds",
+        steps.duration()
+    );
+    let samples = calibration::ride(equipment, &steps, shutdown_rx).await?;
+    let Some(estimate) = calibration::estimate(&steps, &samples, calibrate.max_latency) else {
+        anyhow::bail!(
+            "the equipment didn't follow the steps closely enough to measure its latency, try longer steps or a steadier cadence"
+        );
+    };
+
+    let offset = (estimate.latency * 1000.) as f32;
+    println!(
+        "the {} follows the level {offset:.0} ms late (correlation {:.2})",
+        if estimate.from_resistance { "resistance" } else { "power" },
+        estimate.correlation
+    );
+    if calibrate.dry_run {
+        println!("pass --offset {offset:.0} to ride with it");
+    } else {
+        let path = config::save_offset(args.config.as_deref(), &args.exercise_equipment_type, offset)?;
+        println!(
+            "saved as the offset of equipment type {} in {}",
+            args.exercise_equipment_type,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// ride every level at a few cadences, and save the level × cadence → watts model fitted through the readings
+async fn calibrate_power(
+    args: &cli::Args,
+    calibrate: &cli::CalibrateArgs,
+    equipment: &dyn kondis::Equipment,
+    low: i16,
+    high: i16,
+    shutdown_rx: &Receiver<()>,
+) -> anyhow::Result<()> {
+    if calibrate.cadences.len() < 2 {
+        anyhow::bail!("the power sweep needs at least two cadences to tell them apart");
+    }
+    let sweep = calibration::Sweep::new(low, high, calibrate.steps, &calibrate.cadences, calibrate.hold);
+    println!(
+        "the level goes from {low} to {high} over the next {:.0} seconds, pedal at the cadence asked for",
+        sweep.duration()
+    );
+    let points = calibration::sweep(equipment, &sweep, shutdown_rx).await?;
+    let Some(model) = power::PowerModel::fit(&points) else {
+        anyhow::bail!(
+            "{} readings aren't enough to fit a power model, make sure the equipment reports power and cadence",
+            points.len()
+        );
+    };
+
+    println!("fitted {} readings, off by {:.1} W on average", points.len(), model.error(&points));
+    print!("{:>8}", "level");
+    for cadence in &calibrate.cadences {
+        print!("{:>9}", format!("{cadence:.0} rpm"));
+    }
+    println!();
+    for level in [low, (low + high) / 2, high] {
+        print!("{level:>8}");
+        for &cadence in &calibrate.cadences {
+            print!("{:>9}", format!("{:.0} W", model.watts(level as f64, cadence)));
+        }
+        println!();
+    }
+    if calibrate.dry_run {
+        println!("coefficients: {:?}", model.coefficients());
+    } else {
+        let path = config::save_power_model(args.config.as_deref(), &args.exercise_equipment_type, model.coefficients())?;
+        println!(
+            "saved as the power model of equipment type {} in {}, ride with --target-watts LOW:HIGH",
+            args.exercise_equipment_type,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// whether we've been asked to shut down, or the music player is done (or gone)
+fn playback_over(shutdown_rx: &Receiver<()>, stop_rx: &Receiver<()>) -> bool {
+    shutdown_rx.try_recv().is_ok() || !matches!(stop_rx.try_recv(), Err(TryRecvError::Empty))
+}
+
+/// the tempo at the given position, and how sure we are of it if it was detected
+///
+/// the section and key of the song are tacked on too if they're known, along with cues from the
+/// ride chart and a heads up for a modulation or a jump in brightness that just happened
+fn tempo_state(profile: &profile::RideProfile, position: f64) -> String {
+    let mut state = match &profile.tempo {
+        Some(tempo) if tempo.source == profile::TempoSource::Detected => format!(
+            "{:03.0} bpm ({:02.0}%)",
+            tempo.bpm_at(position),
+            tempo.confidence * 100.
+        ),
+        Some(tempo) => format!("{:03.0} bpm", tempo.bpm_at(position)),
+        None => String::from("--- bpm"),
+    };
+    if let Some(section) = profile.section_at(position) {
+        state.push_str(&format!(" :: {:<6}", section.label));
+    }
+    let frame = profile.frame_at(position);
+    if let Some(key) = profile
+        .feature("key")
+        .and_then(|curve| curve.values.get(frame))
+        .and_then(|&key| analysis::key_name(key))
+    {
+        state.push_str(&format!(" :: {key:<8}"));
+    }
+    if let Some(cue) = profile.cue_at(position) {
+        state.push_str(&format!(" :: {}", cue.kind.to_string().to_uppercase()));
+        if let Some(text) = &cue.text {
+            state.push_str(&format!(" ({text})"));
+        }
+    }
+    if happened_recently(profile, "key_change", position) {
+        state.push_str(" :: modulation!");
+    } else if happened_recently(profile, "brightness_jump", position) {
+        state.push_str(" :: brighter!");
+    }
+    state
+}
+
+/// whether the named event curve fired within the last few seconds
+fn happened_recently(profile: &profile::RideProfile, name: &str, position: f64) -> bool {
+    let Some(curve) = profile.feature(name) else {
+        return false;
+    };
+    let (start, end) = (profile.frame_at(position - 4.), profile.frame_at(position));
+    curve
+        .values
+        .get(start..=end)
+        .is_some_and(|values| values.iter().any(|&value| value > 0.))
+}
+
 fn print_state(stdout: &mut Stdout, input: String, score: f32) {
     let input = if score > 0. {
         input.black().on_yellow()
@@ -183,7 +732,7 @@ fn print_state(stdout: &mut Stdout, input: String, score: f32) {
         .unwrap();
 }
 
-fn get_score(cadence: f32, bpm: Option<u8>) -> f32 {
+fn get_score(cadence: f32, bpm: Option<f64>) -> f32 {
     if bpm.is_none() {
         return 0.;
     }
@@ -203,13 +752,3 @@ fn get_score(cadence: f32, bpm: Option<u8>) -> f32 {
 
     0.
 }
-
-/// Converts a frequency score (0.0 to 1.0) to a level (1 to 64).
-fn freq_score_to_level(max: i16, score: f64) -> i16 {
-    let old_min = 0.;
-    let old_max = 1.;
-    let new_min = 1.;
-    let new_max = max as f64;
-
-    (((score - old_min) / (old_max - old_min)) * (new_max - new_min) + new_min) as i16
-}
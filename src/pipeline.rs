@@ -0,0 +1,233 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context as _;
+
+use crate::profile::{FeatureCurve, RideProfile};
+
+/// a single transform of the level curve
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stage {
+    /// average over a window of `seconds`, centered on every frame
+    Average { seconds: f64 },
+    /// follow the curve up within `attack` seconds and down within `release` seconds
+    Envelope { attack: f64, release: f64 },
+    /// median over a window of `seconds`, which drops short spikes but keeps edges sharp
+    Median { seconds: f64 },
+    /// ignore changes smaller than `deadband` (in levels from 0.0 to 1.0)
+    Hysteresis { deadband: f64 },
+    /// keep every new level for at least `seconds`
+    Hold { seconds: f64 },
+}
+
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    /// stages look like `name:arg:...`, e.g. `envelope:0.2:2`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let mut parts = s.trim().split(':');
+        let name = parts.next().unwrap_or_default();
+        let args = parts
+            .map(|arg| arg.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("invalid number in pipeline stage `{s}`"))?;
+        if args.iter().any(|&arg| arg < 0.) {
+            anyhow::bail!("pipeline stage `{s}` can't take negative values");
+        }
+        let stage = match (name, args.as_slice()) {
+            ("average", &[seconds]) => Stage::Average { seconds },
+            ("envelope", &[attack, release]) => Stage::Envelope { attack, release },
+            ("median", &[seconds]) => Stage::Median { seconds },
+            ("hysteresis", &[deadband]) => Stage::Hysteresis { deadband },
+            ("hold", &[seconds]) => Stage::Hold { seconds },
+            _ => anyhow::bail!(
+                "unknown pipeline stage `{s}`, expected one of: average:seconds, envelope:attack:release, median:seconds, hysteresis:deadband, hold:seconds"
+            ),
+        };
+        Ok(stage)
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Average { seconds } => write!(f, "average:{seconds}"),
+            Stage::Envelope { attack, release } => write!(f, "envelope:{attack}:{release}"),
+            Stage::Median { seconds } => write!(f, "median:{seconds}"),
+            Stage::Hysteresis { deadband } => write!(f, "hysteresis:{deadband}"),
+            Stage::Hold { seconds } => write!(f, "hold:{seconds}"),
+        }
+    }
+}
+
+/// an ordered chain of transforms between the analyzer output and the levels sent to the equipment
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    pub stages: Vec<Stage>,
+}
+
+impl FromStr for Pipeline {
+    type Err = anyhow::Error;
+
+    /// a comma separated list of stages, e.g. `median:1,envelope:0.2:2,hold:3`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let stages = s
+            .split(',')
+            .filter(|stage| !stage.trim().is_empty())
+            .map(str::parse)
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Pipeline { stages })
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stages: Vec<String> = self.stages.iter().map(Stage::to_string).collect();
+        f.write_str(&stages.join(","))
+    }
+}
+
+impl Pipeline {
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// run the levels of `profile` through the pipeline, keeping the unprocessed curve as the `raw_level` feature
+    pub fn process(&self, profile: &mut RideProfile) {
+        if self.is_empty() {
+            return;
+        }
+        let levels = self.apply(&profile.levels, profile.frame_rate);
+        let raw = std::mem::replace(&mut profile.levels, levels);
+        profile.features.retain(|curve| curve.name != "raw_level");
+        profile.features.push(FeatureCurve {
+            name: String::from("raw_level"),
+            values: raw,
+        });
+    }
+
+    /// run `levels` (sampled at `frame_rate` per second) through every stage in order
+    pub fn apply(&self, levels: &[f64], frame_rate: f64) -> Vec<f64> {
+        let frames = |seconds: f64| (seconds * frame_rate).round() as usize;
+        let mut levels = levels.to_vec();
+        for stage in &self.stages {
+            levels = match *stage {
+                Stage::Average { seconds } => average(&levels, frames(seconds) / 2),
+                Stage::Envelope { attack, release } => envelope(&levels, attack, release, frame_rate),
+                Stage::Median { seconds } => median(&levels, frames(seconds) / 2),
+                Stage::Hysteresis { deadband } => hysteresis(&levels, deadband),
+                Stage::Hold { seconds } => hold(&levels, frames(seconds)),
+            };
+        }
+        levels
+    }
+}
+
+fn average(levels: &[f64], radius: usize) -> Vec<f64> {
+    let mut prefix = vec![0.; levels.len() + 1];
+    for (i, level) in levels.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + level;
+    }
+    (0..levels.len())
+        .map(|i| {
+            let lo = i.saturating_sub(radius);
+            let hi = (i + radius + 1).min(levels.len());
+            (prefix[hi] - prefix[lo]) / (hi - lo) as f64
+        })
+        .collect()
+}
+
+fn envelope(levels: &[f64], attack: f64, release: f64, frame_rate: f64) -> Vec<f64> {
+    // one pole smoothing, reaching ~63% of a step within the attack or release time
+    let coefficient = |seconds: f64| {
+        if seconds <= 0. {
+            1.
+        } else {
+            1. - (-1. / (seconds * frame_rate)).exp()
+        }
+    };
+    let (attack, release) = (coefficient(attack), coefficient(release));
+    let mut current = levels.first().copied().unwrap_or_default();
+    levels
+        .iter()
+        .map(|&level| {
+            let coefficient = if level > current { attack } else { release };
+            current += (level - current) * coefficient;
+            current
+        })
+        .collect()
+}
+
+fn median(levels: &[f64], radius: usize) -> Vec<f64> {
+    let mut window = Vec::with_capacity(radius * 2 + 1);
+    (0..levels.len())
+        .map(|i| {
+            window.clear();
+            window.extend_from_slice(&levels[i.saturating_sub(radius)..(i + radius + 1).min(levels.len())]);
+            let middle = window.len() / 2;
+            *window.select_nth_unstable_by(middle, f64::total_cmp).1
+        })
+        .collect()
+}
+
+fn hysteresis(levels: &[f64], deadband: f64) -> Vec<f64> {
+    let mut current = levels.first().copied().unwrap_or_default();
+    levels
+        .iter()
+        .map(|&level| {
+            if (level - current).abs() > deadband {
+                current = level;
+            }
+            current
+        })
+        .collect()
+}
+
+fn hold(levels: &[f64], frames: usize) -> Vec<f64> {
+    let mut current = levels.first().copied().unwrap_or_default();
+    let mut held = 0;
+    levels
+        .iter()
+        .map(|&level| {
+            held += 1;
+            if level != current && held >= frames {
+                current = level;
+                held = 0;
+            }
+            current
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_apply() -> anyhow::Result<()> {
+        let pipeline: Pipeline = "median:0.3, hysteresis:0.1,hold:0.5".parse()?;
+        assert_eq!(pipeline.to_string(), "median:0.3,hysteresis:0.1,hold:0.5");
+        assert!("wobble:1".parse::<Pipeline>().is_err());
+        assert!("envelope:1".parse::<Pipeline>().is_err());
+
+        // at 10 frames per second: a lone spike, some jitter, and a real step up
+        let mut levels = vec![0.2; 30];
+        levels[5] = 1.;
+        levels[12] = 0.25;
+        levels[20..].iter_mut().for_each(|level| *level = 0.8);
+        let processed = pipeline.apply(&levels, 10.);
+        assert_eq!(processed[5], 0.2);
+        assert_eq!(processed[12], 0.2);
+        assert_eq!(processed[29], 0.8);
+
+        let smoothed = Pipeline {
+            stages: vec![Stage::Envelope {
+                attack: 0.5,
+                release: 0.5,
+            }],
+        }
+        .apply(&levels[15..], 10.);
+        assert!(smoothed[6] > 0.2 && smoothed[6] < 0.8);
+        Ok(())
+    }
+}
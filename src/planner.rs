@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use crate::profile::RideProfile;
+use crate::transfer::Transfer;
+
+/// what the equipment can keep up with
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// how many levels per second the resistance can change, `None` if it can jump right away
+    pub ramp_rate: Option<f64>,
+    /// the shortest time between two writes, in seconds
+    pub write_interval: f64,
+    /// the most writes in any minute, `None` for no limit
+    pub max_writes: Option<usize>,
+}
+
+/// a level to set on the equipment, `time` seconds into the track
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Write {
+    pub time: f64,
+    pub level: i16,
+}
+
+/// the writes for a whole track, worked out before it plays
+#[derive(Debug, Clone, Default)]
+pub struct Plan {
+    pub writes: Vec<Write>,
+}
+
+impl Plan {
+    /// plan the writes for `profile` within `limits`
+    ///
+    /// since the whole track is known up front, the resistance starts ramping ahead of big
+    /// changes so it arrives on time, instead of trailing behind them. writes can still trail by
+    /// up to a write interval, as nothing can be written in between
+    pub fn new(profile: &RideProfile, transfer: &Transfer, limits: &Limits) -> Self {
+        let mut levels: Vec<f64> = profile
+            .levels
+            .iter()
+            .map(|&value| transfer.level(value) as f64)
+            .collect();
+
+        // walking backwards, every frame can be at most a frame's worth of ramping away from the
+        // next one. that pulls the start of every change forward until the ramp fits
+        if let Some(ramp_rate) = limits.ramp_rate {
+            let step = ramp_rate / profile.frame_rate;
+            for i in (0..levels.len().saturating_sub(1)).rev() {
+                let next = levels[i + 1];
+                levels[i] = levels[i].clamp(next - step, next + step);
+            }
+        }
+
+        let mut writes: Vec<Write> = Vec::new();
+        let mut recent: VecDeque<f64> = VecDeque::new();
+        for (frame, level) in levels.iter().enumerate() {
+            let time = frame as f64 / profile.frame_rate;
+            let level = level.round() as i16;
+            if let Some(last) = writes.last()
+                && (last.level == level || time - last.time < limits.write_interval)
+            {
+                continue;
+            }
+            while recent.front().is_some_and(|&write| time - write >= 60.) {
+                recent.pop_front();
+            }
+            if limits.max_writes.is_some_and(|max_writes| recent.len() >= max_writes) {
+                continue;
+            }
+            recent.push_back(time);
+            writes.push(Write { time, level });
+        }
+        Plan { writes }
+    }
+
+    /// the level that should be set `position` seconds into the track
+    pub fn level_at(&self, position: f64) -> Option<i16> {
+        let index = self.writes.partition_point(|write| write.time <= position);
+        self.writes.get(index.saturating_sub(1)).map(|write| write.level)
+    }
+}
+
+/// plans every track as it comes up, and looks up what to write
+pub struct Planner {
+    transfer: Transfer,
+    limits: Limits,
+    current: Option<(Arc<RideProfile>, Plan)>,
+}
+
+impl Planner {
+    pub fn new(transfer: Transfer, limits: Limits) -> Self {
+        Planner {
+            transfer,
+            limits,
+            current: None,
+        }
+    }
+
+    /// the planned level `position` seconds into `profile`
+    pub fn level_at(&mut self, profile: &Arc<RideProfile>, position: f64) -> i16 {
+        let plan = match &self.current {
+            Some((planned, plan)) if Arc::ptr_eq(planned, profile) => plan,
+            _ => {
+                let plan = Plan::new(profile, &self.transfer, &self.limits);
+                &self.current.insert((profile.clone(), plan)).1
+            }
+        };
+        plan.level_at(position).unwrap_or(self.transfer.min)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::TrackInfo;
+    use crate::transfer::Curve;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_plan() {
+        // 10 frames per second: a minute at level 1, then a jump to 21
+        let mut profile = RideProfile::new(
+            TrackInfo {
+                path: PathBuf::from("song.flac"),
+                sample_rate: 44_100,
+                channels: 2,
+                duration: 120.,
+                metadata: Default::default(),
+            },
+            None,
+            10.,
+        );
+        profile.levels = (0..1200).map(|frame| if frame < 600 { 0. } else { 1. }).collect();
+        let transfer = Transfer {
+            curve: Curve::Linear,
+            min: 1,
+            max: 21,
+        };
+        let limits = Limits {
+            ramp_rate: Some(2.),
+            write_interval: 1.,
+            max_writes: Some(12),
+        };
+        let plan = Plan::new(&profile, &transfer, &limits);
+
+        // ramping 20 levels at 2 per second starts about 10 seconds early, and gets there on time
+        assert_eq!(plan.level_at(49.), Some(1));
+        assert!(plan.level_at(55.).is_some_and(|level| level > 1 && level < 21));
+        assert!(plan.level_at(60.).is_some_and(|level| level >= 20));
+        assert_eq!(plan.level_at(61.), Some(21));
+        for pair in plan.writes.windows(2) {
+            assert!(pair[1].time - pair[0].time >= 1.);
+            assert!(pair[1].level - pair[0].level <= 2);
+        }
+        assert!(plan.writes.len() <= 12);
+    }
+}
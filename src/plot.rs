@@ -0,0 +1,196 @@
+//! plots of level curves, with one panel per track side by side
+
+use std::fmt::Write as _;
+use std::path::Path;
+
+use anyhow::Context as _;
+use tiny_skia::{Color, Paint, PathBuilder, Pixmap, Rect, Stroke, Transform};
+
+const PANEL_WIDTH: f32 = 480.;
+const PANEL_HEIGHT: f32 = 280.;
+/// room around the plot area for the title, the axis labels and the legend
+const MARGIN: (f32, f32, f32, f32) = (40., 30., 12., 40.);
+
+/// a curve, with values from 0.0 to 1.0 at seconds into the track
+pub struct Series {
+    pub name: &'static str,
+    pub color: [u8; 3],
+    pub points: Vec<(f64, f64)>,
+}
+
+/// the curves of a single track
+pub struct Panel {
+    pub title: String,
+    /// length of the track in seconds
+    pub duration: f64,
+    pub series: Vec<Series>,
+}
+
+impl Panel {
+    /// where a value ends up in the image, for the `index`th panel
+    fn position(&self, index: usize, (time, value): (f64, f64)) -> (f32, f32) {
+        let (left, top, right, bottom) = MARGIN;
+        let width = PANEL_WIDTH - left - right;
+        let height = PANEL_HEIGHT - top - bottom;
+        let x = index as f32 * PANEL_WIDTH + left + (time / self.duration.max(1.)) as f32 * width;
+        let y = top + (1. - value.clamp(0., 1.) as f32) * height;
+        (x, y)
+    }
+
+    /// the corners of the plot area, for the `index`th panel
+    fn area(&self, index: usize) -> ((f32, f32), (f32, f32)) {
+        (
+            self.position(index, (0., 1.)),
+            self.position(index, (self.duration.max(1.), 0.)),
+        )
+    }
+
+    /// full minutes into the track, to put a tick at
+    fn minutes(&self) -> impl Iterator<Item = f64> {
+        (0..=(self.duration / 60.) as usize).map(|minute| minute as f64 * 60.)
+    }
+}
+
+fn size(panels: &[Panel]) -> (f32, f32) {
+    (PANEL_WIDTH * panels.len().max(1) as f32, PANEL_HEIGHT)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+pub fn svg(panels: &[Panel]) -> String {
+    let (width, height) = size(panels);
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}" font-family="sans-serif" font-size="11">"#
+    );
+    let _ = writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#);
+    for (index, panel) in panels.iter().enumerate() {
+        let ((left, top), (right, bottom)) = panel.area(index);
+        let _ = writeln!(
+            svg,
+            r#"<text x="{left}" y="{}" font-weight="bold">{}</text>"#,
+            top - 10.,
+            escape(&panel.title)
+        );
+        for step in 0..=4 {
+            let value = step as f64 / 4.;
+            let (_, y) = panel.position(index, (0., value));
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{left}" y1="{y}" x2="{right}" y2="{y}" stroke="#e0e0e0"/><text x="{}" y="{}" text-anchor="end">{value:.2}</text>"##,
+                left - 4.,
+                y + 4.
+            );
+        }
+        for minute in panel.minutes() {
+            let (x, _) = panel.position(index, (minute, 0.));
+            let _ = writeln!(
+                svg,
+                r##"<line x1="{x}" y1="{bottom}" x2="{x}" y2="{}" stroke="#808080"/><text x="{x}" y="{}" text-anchor="middle">{}:00</text>"##,
+                bottom + 4.,
+                bottom + 16.,
+                minute / 60.
+            );
+        }
+        let _ = writeln!(
+            svg,
+            r##"<rect x="{left}" y="{top}" width="{}" height="{}" fill="none" stroke="#808080"/>"##,
+            right - left,
+            bottom - top
+        );
+        for series in &panel.series {
+            let points: Vec<String> = series
+                .points
+                .iter()
+                .map(|&point| {
+                    let (x, y) = panel.position(index, point);
+                    format!("{x:.1},{y:.1}")
+                })
+                .collect();
+            let [r, g, b] = series.color;
+            let _ = writeln!(
+                svg,
+                r#"<polyline points="{}" fill="none" stroke="rgb({r},{g},{b})" stroke-width="1.5"/>"#,
+                points.join(" ")
+            );
+        }
+        // a legend under every panel, as they don't necessarily have the same curves
+        let mut x = left;
+        for series in &panel.series {
+            let [r, g, b] = series.color;
+            let _ = writeln!(
+                svg,
+                r#"<rect x="{x}" y="{}" width="10" height="10" fill="rgb({r},{g},{b})"/><text x="{}" y="{}">{}</text>"#,
+                bottom + 22.,
+                x + 14.,
+                bottom + 31.,
+                series.name
+            );
+            x += 20. + series.name.len() as f32 * 7.;
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// the same plot as [`svg`], but without any text
+pub fn png(panels: &[Panel], path: &Path) -> anyhow::Result<()> {
+    let (width, height) = size(panels);
+    let mut pixmap = Pixmap::new(width as u32, height as u32).context("plot is too large")?;
+    pixmap.fill(Color::WHITE);
+    let paint = |[r, g, b]: [u8; 3]| {
+        let mut paint = Paint::default();
+        paint.set_color_rgba8(r, g, b, 255);
+        paint.anti_alias = true;
+        paint
+    };
+    let stroke = |width: f32| Stroke {
+        width,
+        ..Stroke::default()
+    };
+    let mut line = |points: &[(f32, f32)], color: [u8; 3], width: f32| {
+        let mut path = PathBuilder::new();
+        for (i, &(x, y)) in points.iter().enumerate() {
+            if i == 0 {
+                path.move_to(x, y);
+            } else {
+                path.line_to(x, y);
+            }
+        }
+        if let Some(path) = path.finish() {
+            pixmap.stroke_path(&path, &paint(color), &stroke(width), Transform::identity(), None);
+        }
+    };
+    for (index, panel) in panels.iter().enumerate() {
+        let ((left, top), (right, bottom)) = panel.area(index);
+        for step in 0..=4 {
+            let (_, y) = panel.position(index, (0., step as f64 / 4.));
+            line(&[(left, y), (right, y)], [224, 224, 224], 1.);
+        }
+        for minute in panel.minutes() {
+            let (x, _) = panel.position(index, (minute, 0.));
+            line(&[(x, bottom), (x, bottom + 4.)], [128, 128, 128], 1.);
+        }
+        if let Some(rect) = Rect::from_ltrb(left, top, right, bottom) {
+            line(
+                &[(rect.left(), rect.top()), (rect.right(), rect.top()), (rect.right(), rect.bottom()), (rect.left(), rect.bottom()), (rect.left(), rect.top())],
+                [128, 128, 128],
+                1.,
+            );
+        }
+        for series in &panel.series {
+            let points: Vec<(f32, f32)> = series
+                .points
+                .iter()
+                .map(|&point| panel.position(index, point))
+                .collect();
+            line(&points, series.color, 1.5);
+        }
+    }
+    pixmap
+        .save_png(path)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
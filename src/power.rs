@@ -0,0 +1,296 @@
+//! what a level is worth in watts, for equipment that only takes levels
+//!
+//! the same level is a lot more work at 100 rpm than at 60, so a ride that targets watts has to pick
+//! its level by the cadence, from a model fitted to a calibration sweep
+
+/// levels and cadences are scaled down before fitting, so the terms of the surface stay within a few orders of magnitude
+const LEVEL_SCALE: f64 = 10.;
+const CADENCE_SCALE: f64 = 100.;
+/// number of terms of the surface
+const TERMS: usize = 7;
+
+/// power at a level and cadence, as measured during calibration
+#[derive(Debug, Clone, Copy)]
+pub struct Point {
+    pub level: f64,
+    pub cadence: f64,
+    pub watts: f64,
+}
+
+/// a surface through level × cadence → watts
+///
+/// power is torque times cadence, and torque grows with the level (not quite linearly), so the
+/// surface is a quadratic in the level for every cadence, with some friction on top
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerModel {
+    coefficients: [f64; TERMS],
+}
+
+fn terms(level: f64, cadence: f64) -> [f64; TERMS] {
+    let (l, c) = (level / LEVEL_SCALE, cadence / CADENCE_SCALE);
+    [1., l, c, l * c, l * l, l * l * c, c * c]
+}
+
+impl PowerModel {
+    /// least squares fit through the points, nothing if they don't pin the surface down
+    pub fn fit(points: &[Point]) -> Option<Self> {
+        if points.len() < TERMS {
+            return None;
+        }
+        // normal equations, solved by gaussian elimination with partial pivoting
+        let mut matrix = [[0.; TERMS + 1]; TERMS];
+        for point in points {
+            let row = terms(point.level, point.cadence);
+            for i in 0..TERMS {
+                for j in 0..TERMS {
+                    matrix[i][j] += row[i] * row[j];
+                }
+                matrix[i][TERMS] += row[i] * point.watts;
+            }
+        }
+        for column in 0..TERMS {
+            let pivot = (column..TERMS).max_by(|&a, &b| matrix[a][column].abs().total_cmp(&matrix[b][column].abs()))?;
+            if matrix[pivot][column].abs() < 1e-9 {
+                return None;
+            }
+            matrix.swap(column, pivot);
+            let pivot_row = matrix[column];
+            for (row, values) in matrix.iter_mut().enumerate() {
+                if row != column {
+                    let factor = values[column] / pivot_row[column];
+                    for (value, pivot_value) in values.iter_mut().zip(&pivot_row).skip(column) {
+                        *value -= factor * pivot_value;
+                    }
+                }
+            }
+        }
+        let mut coefficients = [0.; TERMS];
+        for (i, coefficient) in coefficients.iter_mut().enumerate() {
+            *coefficient = matrix[i][TERMS] / matrix[i][i];
+        }
+        Some(PowerModel { coefficients })
+    }
+
+    pub fn watts(&self, level: f64, cadence: f64) -> f64 {
+        terms(level, cadence)
+            .iter()
+            .zip(&self.coefficients)
+            .map(|(term, coefficient)| term * coefficient)
+            .sum()
+    }
+
+    /// the level within `min..=max` that comes closest to `watts` at `cadence`
+    pub fn level_for(&self, watts: f64, cadence: f64, min: i16, max: i16) -> i16 {
+        (min..=max)
+            .min_by(|&a, &b| {
+                let error = |level: i16| (self.watts(level as f64, cadence) - watts).abs();
+                error(a).total_cmp(&error(b))
+            })
+            .unwrap_or(min)
+    }
+
+    /// root mean square error over the points, in watts
+    pub fn error(&self, points: &[Point]) -> f64 {
+        let squares: f64 = points
+            .iter()
+            .map(|point| (self.watts(point.level, point.cadence) - point.watts).powi(2))
+            .sum();
+        (squares / points.len().max(1) as f64).sqrt()
+    }
+
+    /// the coefficients, to be saved in the config
+    pub fn coefficients(&self) -> &[f64] {
+        &self.coefficients
+    }
+}
+
+impl TryFrom<&[f64]> for PowerModel {
+    type Error = anyhow::Error;
+
+    fn try_from(coefficients: &[f64]) -> anyhow::Result<Self> {
+        Ok(PowerModel {
+            coefficients: coefficients.try_into().map_err(|_| {
+                anyhow::anyhow!(
+                    "a power model has {TERMS} coefficients, got {}, run `music-rider calibrate power` again",
+                    coefficients.len()
+                )
+            })?,
+        })
+    }
+}
+
+/// the watts the easiest and the hardest parts of a ride ask for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WattsRange {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl WattsRange {
+    /// the watts for a share (0.0 to 1.0) of the range
+    pub fn at(&self, share: f64) -> f64 {
+        self.low + share.clamp(0., 1.) * (self.high - self.low)
+    }
+}
+
+impl WattsRange {
+    /// "LOW:HIGH" in watts (e.g. "100:250"), or in percent of the FTP (e.g. "55%:105%")
+    pub fn parse(s: &str, ftp: Option<f64>) -> anyhow::Result<Self> {
+        let (low, high) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("expected LOW:HIGH watts, got `{s}`"))?;
+        let watts = |value: &str| -> anyhow::Result<f64> {
+            let value = value.trim();
+            match value.strip_suffix('%') {
+                Some(percent) => {
+                    let ftp = ftp.ok_or_else(|| anyhow::anyhow!("`{value}` of what? set --ftp to ride in percent of it"))?;
+                    Ok(percent.trim().parse::<f64>()? / 100. * ftp)
+                }
+                None => Ok(value.parse()?),
+            }
+        };
+        let (low, high) = (watts(low)?, watts(high)?);
+        if !(0. <= low && low < high) {
+            anyhow::bail!("the watts have to go up from 0 or more, got `{s}`");
+        }
+        Ok(WattsRange { low, high })
+    }
+}
+
+/// how to ride the equipment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ControlMode {
+    /// ERG for equipment that holds target watts itself, levels for equipment that doesn't
+    Auto,
+    /// send resistance levels
+    Level,
+    /// send target watts, and let the equipment hold them whatever the cadence. refused for equipment that
+    /// takes levels
+    Erg,
+}
+
+/// what gets sent to the equipment for a planned level
+#[derive(Debug, Clone)]
+pub enum Control {
+    /// the planned level as is
+    Level,
+    /// the planned level stands for watts between `range` (unless the training zones ask for some),
+    /// which are sent as is in ERG mode, or turned back into the level that gives them at the current
+    /// cadence with a power model
+    Watts {
+        /// nothing when riding by zones
+        range: Option<WattsRange>,
+        /// the levels the plan goes between
+        min: i16,
+        max: i16,
+        /// nothing in ERG mode
+        model: Option<PowerModel>,
+    },
+}
+
+/// what to send to the equipment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Command {
+    /// for `set_target_power`, a level or watts depending on the mode
+    pub value: i16,
+    /// the level to show
+    pub level: i16,
+    pub watts: Option<f64>,
+}
+
+impl Control {
+    pub fn is_erg(&self) -> bool {
+        matches!(self, Control::Watts { model: None, .. })
+    }
+
+    /// what to send for the planned level, or for the watts of the training zones if they're laid over the track
+    pub fn command(&self, planned: i16, cadence: Option<f64>, zone_watts: Option<f64>) -> Command {
+        let level = Command {
+            value: planned,
+            level: planned,
+            watts: None,
+        };
+        let Control::Watts { range, min, max, model } = self else {
+            return level;
+        };
+        let share = if max > min {
+            (planned - min) as f64 / (max - min) as f64
+        } else {
+            1.
+        };
+        let Some(watts) = zone_watts.or(range.map(|range| range.at(share))) else {
+            return level;
+        };
+        match (model, cadence) {
+            (None, _) => Command {
+                value: watts.round() as i16,
+                level: planned,
+                watts: Some(watts),
+            },
+            (Some(model), Some(cadence)) => {
+                let level = model.level_for(watts, cadence, *min, *max);
+                Command {
+                    value: level,
+                    level,
+                    watts: Some(watts),
+                }
+            }
+            // the level can't be picked by the cadence until there is one
+            (Some(_), None) => level,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_and_invert() {
+        // torque grows with the level, a little faster towards the top, plus some friction
+        let bike = |level: f64, cadence: f64| cadence * (0.3 + 0.06 * level + 0.0008 * level * level) + 5.;
+        let mut points = Vec::new();
+        for level in (1..=32).step_by(4) {
+            for cadence in [55., 70., 85., 100.] {
+                // readings are whole watts, and never quite steady
+                let wobble = if (level as usize + cadence as usize).is_multiple_of(2) { 2. } else { -2. };
+                points.push(Point {
+                    level: level as f64,
+                    cadence,
+                    watts: (bike(level as f64, cadence) + wobble).round(),
+                });
+            }
+        }
+
+        let model = PowerModel::fit(&points).unwrap();
+        assert!(model.error(&points) < 3., "{}", model.error(&points));
+        assert!((model.watts(20., 80.) - bike(20., 80.)).abs() < 5.);
+        // the same watts take a higher level at a lower cadence
+        assert_eq!(model.level_for(bike(20., 80.), 80., 1, 32), 20);
+        assert!(model.level_for(bike(20., 80.), 60., 1, 32) > 20);
+        assert_eq!(PowerModel::try_from(model.coefficients()).unwrap(), model);
+
+        let range = WattsRange::parse("100:250", None).unwrap();
+        assert_eq!(range.at(0.5), 175.);
+        assert!(WattsRange::parse("250:100", None).is_err());
+        assert!(WattsRange::parse("50%:100%", None).is_err());
+        assert_eq!(WattsRange::parse("50%:100%", Some(240.)).unwrap(), WattsRange { low: 120., high: 240. });
+
+        let erg = Control::Watts {
+            range: Some(range),
+            min: 1,
+            max: 11,
+            model: None,
+        };
+        assert_eq!(erg.command(6, None, None).value, 175);
+        assert_eq!(erg.command(6, None, Some(130.)).value, 130);
+        let modeled = Control::Watts {
+            range: Some(range),
+            min: 1,
+            max: 32,
+            model: Some(model),
+        };
+        assert_eq!(modeled.command(32, None, None).value, 32);
+        assert_eq!(modeled.command(1, Some(80.), None).watts, Some(100.));
+    }
+}
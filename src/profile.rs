@@ -0,0 +1,301 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::audio::TrackMetadata;
+
+/// bump this whenever the layout of `RideProfile` changes, so stale caches get ignored
+pub const PROFILE_VERSION: u32 = 6;
+
+/// what we know about the track a profile was computed from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackInfo {
+    pub path: PathBuf,
+    pub sample_rate: u32,
+    pub channels: usize,
+    /// length of the track in seconds
+    pub duration: f64,
+    /// what its tags say
+    pub metadata: TrackMetadata,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TempoSource {
+    /// read from the track's tags
+    Tag,
+    /// estimated by the beat tracker
+    Detected,
+    /// set by hand in a ride chart
+    Chart,
+    /// timed by hand in a rhythm game chart
+    Beatmap,
+}
+
+/// a stretch of the track with a steady tempo
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TempoSegment {
+    /// seconds into the track where this tempo starts
+    pub start: f64,
+    pub bpm: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tempo {
+    /// the dominant tempo of the track
+    pub bpm: f64,
+    pub source: TempoSource,
+    /// how sure we are of the tempo, from 0.0 to 1.0
+    pub confidence: f64,
+    /// beat positions in seconds, if known
+    pub beats: Vec<f64>,
+    /// tempo changes over the track, sorted by start. empty when the tempo is steady
+    pub map: Vec<TempoSegment>,
+}
+
+impl Tempo {
+    pub fn from_tag(bpm: f64) -> Self {
+        Tempo {
+            bpm,
+            source: TempoSource::Tag,
+            confidence: 1.,
+            beats: Vec::new(),
+            map: Vec::new(),
+        }
+    }
+
+    pub fn bpm_at(&self, position: f64) -> f64 {
+        self.map
+            .iter()
+            .take_while(|segment| segment.start <= position)
+            .last()
+            .map_or(self.bpm, |segment| segment.bpm)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SectionLabel {
+    Intro,
+    Verse,
+    Chorus,
+    Drop,
+    Outro,
+}
+
+impl std::fmt::Display for SectionLabel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SectionLabel::Intro => "intro",
+            SectionLabel::Verse => "verse",
+            SectionLabel::Chorus => "chorus",
+            SectionLabel::Drop => "drop",
+            SectionLabel::Outro => "outro",
+        })
+    }
+}
+
+/// a part of the song, like a verse or a chorus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Section {
+    /// seconds into the track
+    pub start: f64,
+    pub end: f64,
+    pub label: SectionLabel,
+    /// the average level within the section
+    pub level: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CueKind {
+    Stand,
+    Sit,
+    Sprint,
+}
+
+impl std::fmt::Display for CueKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CueKind::Stand => "stand up",
+            CueKind::Sit => "sit down",
+            CueKind::Sprint => "sprint",
+        })
+    }
+}
+
+/// something the rider should do for a while, from a ride chart
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cue {
+    /// seconds into the track
+    pub start: f64,
+    pub end: f64,
+    pub kind: CueKind,
+    pub text: Option<String>,
+}
+
+/// a named curve produced by an analyzer, one value per profile frame
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeatureCurve {
+    pub name: String,
+    pub values: Vec<f64>,
+}
+
+/// the precomputed ride for a single track
+///
+/// every curve shares the same time axis: frame `i` starts at `i / frame_rate` seconds into the track
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RideProfile {
+    pub version: u32,
+    pub track: TrackInfo,
+    pub tempo: Option<Tempo>,
+    /// profile frames per second
+    pub frame_rate: f64,
+    pub features: Vec<FeatureCurve>,
+    /// the song structure, if it was detected
+    pub sections: Vec<Section>,
+    /// cues for the rider, from a ride chart
+    pub cues: Vec<Cue>,
+    /// the level curve derived from the features, from 0.0 (easiest) to 1.0 (hardest)
+    pub levels: Vec<f64>,
+}
+
+impl RideProfile {
+    pub fn new(track: TrackInfo, tempo: Option<Tempo>, frame_rate: f64) -> Self {
+        RideProfile {
+            version: PROFILE_VERSION,
+            track,
+            tempo,
+            frame_rate,
+            features: Vec::new(),
+            sections: Vec::new(),
+            cues: Vec::new(),
+            levels: Vec::new(),
+        }
+    }
+
+    pub fn feature(&self, name: &str) -> Option<&FeatureCurve> {
+        self.features.iter().find(|curve| curve.name == name)
+    }
+
+    /// append a value to the named curve, creating the curve if needed
+    pub fn push_feature(&mut self, name: &str, value: f64) {
+        match self.features.iter_mut().find(|curve| curve.name == name) {
+            Some(curve) => curve.values.push(value),
+            None => self.features.push(FeatureCurve {
+                name: name.to_string(),
+                values: vec![value],
+            }),
+        }
+    }
+
+    /// the frame that plays at `position` seconds into the track
+    pub fn frame_at(&self, position: f64) -> usize {
+        let frame = (position.max(0.) * self.frame_rate) as usize;
+        frame.min(self.levels.len().saturating_sub(1))
+    }
+
+    /// the level at `position` seconds into the track
+    pub fn level_at(&self, position: f64) -> Option<f64> {
+        self.levels.get(self.frame_at(position)).copied()
+    }
+
+    /// the section playing at `position` seconds into the track
+    pub fn section_at(&self, position: f64) -> Option<&Section> {
+        self.sections
+            .iter()
+            .find(|section| position >= section.start && position < section.end)
+    }
+
+    /// the cue for the rider at `position` seconds into the track
+    pub fn cue_at(&self, position: f64) -> Option<&Cue> {
+        self.cues
+            .iter()
+            .find(|cue| position >= cue.start && position < cue.end)
+    }
+
+    /// the tempo at `position` seconds into the track
+    pub fn bpm_at(&self, position: f64) -> Option<f64> {
+        self.tempo.as_ref().map(|tempo| tempo.bpm_at(position))
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+
+    /// write the profile to disk, as JSON if the extension says so and binary otherwise
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if is_json(path) {
+            std::fs::write(path, self.to_json()?)?;
+        } else {
+            std::fs::write(path, self.to_bytes()?)?;
+        }
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let profile = if is_json(path) {
+            Self::from_json(&std::fs::read_to_string(path)?)?
+        } else {
+            Self::from_bytes(&std::fs::read(path)?)?
+        };
+        if profile.version != PROFILE_VERSION {
+            anyhow::bail!(
+                "profile {} has version {}, expected {PROFILE_VERSION}",
+                path.display(),
+                profile.version
+            );
+        }
+        Ok(profile)
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() -> anyhow::Result<()> {
+        let mut profile = RideProfile::new(
+            TrackInfo {
+                path: PathBuf::from("song.flac"),
+                sample_rate: 44_100,
+                channels: 2,
+                duration: 1.,
+                metadata: Default::default(),
+            },
+            Some(Tempo::from_tag(128.)),
+            44_100. / 4096.,
+        );
+        profile.push_feature("score", 0.25);
+        profile.push_feature("score", 0.75);
+        profile.levels = vec![0.25, 0.75];
+
+        let json = RideProfile::from_json(&profile.to_json()?)?;
+        let bytes = RideProfile::from_bytes(&profile.to_bytes()?)?;
+        for decoded in [json, bytes] {
+            assert_eq!(decoded.levels, profile.levels);
+            assert_eq!(decoded.feature("score").unwrap().values, vec![0.25, 0.75]);
+            assert_eq!(decoded.bpm_at(0.), Some(128.));
+        }
+        assert_eq!(profile.level_at(0.5), Some(0.75));
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::Context as _;
+use serde::Deserialize;
+
+use crate::profile::{Cue, CueKind, RideProfile, Tempo, TempoSegment, TempoSource};
+
+/// how long a cue stays up unless the chart says otherwise, in seconds
+const CUE_SECONDS: f64 = 10.;
+
+/// how a ride chart combines with the analyzed levels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// the chart only changes the levels within its entries
+    #[default]
+    Layer,
+    /// the chart is the whole ride, analyzed levels are ignored
+    Replace,
+}
+
+/// seconds into the track, written as a number of seconds or as `m:ss` / `h:mm:ss`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Timestamp {
+    Seconds(f64),
+    Text(String),
+}
+
+impl Timestamp {
+    fn seconds(&self) -> anyhow::Result<f64> {
+        let seconds = match self {
+            Timestamp::Seconds(seconds) => *seconds,
+            Timestamp::Text(text) => text.split(':').try_fold(0., |total, part| {
+                let part: f64 = part
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("`{text}` isn't a time, use seconds or m:ss"))?;
+                anyhow::Ok(total * 60. + part)
+            })?,
+        };
+        if seconds.is_nan() || seconds < 0. {
+            anyhow::bail!("times can't be negative, got {seconds}");
+        }
+        Ok(seconds)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LevelEntry {
+    at: Timestamp,
+    until: Option<Timestamp>,
+    /// the level, from 0.0 (easiest) to 1.0 (hardest)
+    level: Option<f64>,
+    /// added to whatever the level would have been
+    boost: Option<f64>,
+    /// seconds it takes to get there
+    #[serde(default)]
+    ramp: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CueEntry {
+    at: Timestamp,
+    cue: CueKind,
+    /// seconds the cue stays up
+    duration: Option<f64>,
+    text: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TempoEntry {
+    at: Timestamp,
+    bpm: f64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    #[serde(default)]
+    mode: Mode,
+    bpm: Option<f64>,
+    #[serde(default)]
+    level: Vec<LevelEntry>,
+    #[serde(default)]
+    cue: Vec<CueEntry>,
+    #[serde(default)]
+    tempo: Vec<TempoEntry>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Change {
+    Level(f64),
+    Boost(f64),
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    start: f64,
+    end: Option<f64>,
+    change: Change,
+    ramp: f64,
+}
+
+/// a hand written ride for a track, kept next to it as e.g. `song.flac.ride.toml`
+///
+/// ```toml
+/// mode = "layer" # or "replace" to ignore the analysis altogether
+/// bpm = 128
+///
+/// [[level]]
+/// at = "1:30"
+/// until = "2:00"
+/// level = 0.9
+/// ramp = 10
+///
+/// [[cue]]
+/// at = 95
+/// cue = "stand" # or "sit", "sprint"
+/// text = "climb!"
+/// ```
+#[derive(Debug, Clone)]
+pub struct RideChart {
+    mode: Mode,
+    spans: Vec<Span>,
+    cues: Vec<(f64, f64, CueKind, Option<String>)>,
+    tempo: Option<(f64, Vec<TempoSegment>)>,
+}
+
+impl RideChart {
+    /// where the ride chart of `track` lives
+    pub fn path(track: &Path) -> PathBuf {
+        let mut path = track.as_os_str().to_owned();
+        path.push(".ride.toml");
+        PathBuf::from(path)
+    }
+
+    /// read the ride chart of `track`, if it has one
+    pub fn load(track: &Path) -> anyhow::Result<Option<Self>> {
+        let path = Self::path(track);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read ride chart {}", path.display()))?;
+        let chart = text
+            .parse()
+            .with_context(|| format!("invalid ride chart {}", path.display()))?;
+        Ok(Some(chart))
+    }
+
+    /// change the levels, cues and tempo of `profile` according to the chart
+    pub fn apply(&self, profile: &mut RideProfile) {
+        let levels = &mut profile.levels;
+        if self.mode == Mode::Replace {
+            levels.iter_mut().for_each(|level| *level = 0.);
+        }
+        let (frame_rate, frames) = (profile.frame_rate, levels.len());
+        let frame = |seconds: f64| ((seconds * frame_rate) as usize).min(frames);
+        for span in &self.spans {
+            let (start, end) = (frame(span.start), frame(span.end.unwrap_or(f64::INFINITY)));
+            // ramps start from wherever the level was right before
+            let from = start.checked_sub(1).and_then(|i| levels.get(i).copied());
+            for (i, current) in levels.iter_mut().enumerate().take(end).skip(start) {
+                let progress = if span.ramp > 0. {
+                    ((i as f64 / frame_rate - span.start) / span.ramp).min(1.)
+                } else {
+                    1.
+                };
+                let level = match span.change {
+                    Change::Level(level) => {
+                        let from = from.unwrap_or(level);
+                        from + (level - from) * progress
+                    }
+                    Change::Boost(boost) => *current + boost * progress,
+                };
+                *current = level.clamp(0., 1.);
+            }
+        }
+
+        profile.cues = self
+            .cues
+            .iter()
+            .map(|(start, end, kind, text)| Cue {
+                start: *start,
+                end: *end,
+                kind: *kind,
+                text: text.clone(),
+            })
+            .collect();
+
+        if let Some((bpm, map)) = &self.tempo {
+            profile.tempo = Some(Tempo {
+                bpm: *bpm,
+                source: TempoSource::Chart,
+                confidence: 1.,
+                beats: Vec::new(),
+                map: map.clone(),
+            });
+        }
+    }
+}
+
+impl std::str::FromStr for RideChart {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> anyhow::Result<Self> {
+        let file: File = toml::from_str(text)?;
+
+        let mut spans = file
+            .level
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let context = || format!("level entry {} is invalid", i + 1);
+                let start = entry.at.seconds().with_context(context)?;
+                let end = entry
+                    .until
+                    .as_ref()
+                    .map(Timestamp::seconds)
+                    .transpose()
+                    .with_context(context)?;
+                if let Some(end) = end
+                    && end <= start
+                {
+                    anyhow::bail!("level entry {} ends (at {end}s) before it starts (at {start}s)", i + 1);
+                }
+                let change = match (entry.level, entry.boost) {
+                    (Some(level), None) if (0. ..=1.).contains(&level) => Change::Level(level),
+                    (Some(level), None) => {
+                        anyhow::bail!("level entry {} has level {level}, levels go from 0.0 to 1.0", i + 1)
+                    }
+                    (None, Some(boost)) if (-1. ..=1.).contains(&boost) => Change::Boost(boost),
+                    (None, Some(boost)) => {
+                        anyhow::bail!("level entry {} has boost {boost}, boosts go from -1.0 to 1.0", i + 1)
+                    }
+                    _ => anyhow::bail!("level entry {} needs either a `level` or a `boost`", i + 1),
+                };
+                if entry.ramp.is_nan() || entry.ramp < 0. {
+                    anyhow::bail!("level entry {} can't ramp for {} seconds", i + 1, entry.ramp);
+                }
+                Ok(Span {
+                    start,
+                    end,
+                    change,
+                    ramp: entry.ramp,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        spans.sort_by(|a, b| a.start.total_cmp(&b.start));
+        // without an explicit end, an entry lasts until the next one starts
+        for i in 0..spans.len() {
+            if spans[i].end.is_none() {
+                spans[i].end = spans.get(i + 1).map(|next| next.start);
+            }
+        }
+
+        let cues = file
+            .cue
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let start = entry
+                    .at
+                    .seconds()
+                    .with_context(|| format!("cue entry {} is invalid", i + 1))?;
+                let duration = entry.duration.unwrap_or(CUE_SECONDS);
+                if duration.is_nan() || duration <= 0. {
+                    anyhow::bail!("cue entry {} needs a duration above 0, got {duration}", i + 1);
+                }
+                Ok((start, start + duration, entry.cue, entry.text.clone()))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let mut map = file
+            .tempo
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let start = entry
+                    .at
+                    .seconds()
+                    .with_context(|| format!("tempo entry {} is invalid", i + 1))?;
+                if entry.bpm.is_nan() || entry.bpm <= 0. {
+                    anyhow::bail!("tempo entry {} needs a bpm above 0, got {}", i + 1, entry.bpm);
+                }
+                Ok(TempoSegment { start, bpm: entry.bpm })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        map.sort_by(|a, b| a.start.total_cmp(&b.start));
+        if let Some(bpm) = file.bpm
+            && (bpm.is_nan() || bpm <= 0.)
+        {
+            anyhow::bail!("bpm needs to be above 0, got {bpm}");
+        }
+        let tempo = match (file.bpm, map.first()) {
+            (Some(bpm), _) => Some((bpm, map)),
+            (None, Some(first)) => Some((first.bpm, map)),
+            (None, None) => None,
+        };
+
+        Ok(RideChart {
+            mode: file.mode,
+            spans,
+            cues,
+            tempo,
+        })
+    }
+}
+
+/// notices when the ride chart of a track gets created, edited or removed
+pub struct Watcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl Watcher {
+    pub fn new(track: &Path) -> Self {
+        let path = RideChart::path(track);
+        let modified = modified(&path);
+        Watcher { path, modified }
+    }
+
+    /// whether the chart changed since the last call
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::TrackInfo;
+
+    /// a profile of `seconds` at one frame per second, to put charts on
+    fn profile(seconds: usize) -> RideProfile {
+        RideProfile::new(
+            TrackInfo {
+                path: PathBuf::from("song.flac"),
+                sample_rate: 44_100,
+                channels: 2,
+                duration: seconds as f64,
+                metadata: Default::default(),
+            },
+            None,
+            1.,
+        )
+    }
+
+    #[test]
+    fn test_apply() -> anyhow::Result<()> {
+        let chart: RideChart = r#"
+            bpm = 128
+
+            [[level]]
+            at = "0:10"
+            until = 20
+            level = 1.0
+            ramp = 5
+
+            [[level]]
+            at = 30
+            boost = -0.5
+
+            [[cue]]
+            at = "0:12"
+            cue = "stand"
+        "#
+        .parse()?;
+
+        let mut profile = profile(40);
+        profile.levels = vec![0.5; 40];
+        chart.apply(&mut profile);
+        assert_eq!(profile.levels[5], 0.5);
+        assert!(profile.levels[12] > 0.5 && profile.levels[12] < 1.);
+        assert_eq!(profile.levels[16], 1.);
+        assert_eq!(profile.levels[25], 0.5);
+        assert_eq!(profile.levels[35], 0.);
+        assert_eq!(profile.cue_at(15.).map(|cue| cue.kind), Some(CueKind::Stand));
+        assert_eq!(profile.bpm_at(0.), Some(128.));
+
+        let error = "[[level]]\nat = 10\nlevel = 2".parse::<RideChart>().unwrap_err();
+        assert!(format!("{error:#}").contains("level entry 1 has level 2"));
+        assert!("[[level]]\nat = \"1:xx\"\nlevel = 1".parse::<RideChart>().is_err());
+        assert!("[[cue]]\nat = 1\ncue = \"cartwheel\"".parse::<RideChart>().is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_tempo_map() -> anyhow::Result<()> {
+        // out of order, and without a `bpm` to start from
+        let chart: RideChart = r#"
+            [[tempo]]
+            at = "0:20"
+            bpm = 150
+
+            [[tempo]]
+            at = 0
+            bpm = 100
+        "#
+        .parse()?;
+        let mut profile = profile(40);
+        profile.levels = vec![0.5; 40];
+        chart.apply(&mut profile);
+        assert_eq!(profile.bpm_at(19.9), Some(100.));
+        assert_eq!(profile.bpm_at(20.), Some(150.));
+        assert_eq!(profile.bpm_at(30.), Some(150.));
+        Ok(())
+    }
+}
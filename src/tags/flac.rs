@@ -0,0 +1,67 @@
+use std::path::Path;
+
+use anyhow::Context as _;
+
+use super::vorbis::Comments;
+
+const VORBIS_COMMENT: u8 = 4;
+
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+/// the metadata blocks of a FLAC file, and the offset the audio starts at
+fn blocks(file: &[u8]) -> anyhow::Result<(Vec<Block>, usize)> {
+    anyhow::ensure!(file.starts_with(b"fLaC"), "not a FLAC file");
+    let mut offset = 4;
+    let mut blocks = Vec::new();
+    loop {
+        let header = file.get(offset..offset + 4).context("FLAC metadata is cut short")?;
+        let last = header[0] & 0x80 != 0;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let data = file
+            .get(offset + 4..offset + 4 + length)
+            .context("FLAC metadata is cut short")?;
+        blocks.push(Block {
+            kind: header[0] & 0x7f,
+            data: data.to_vec(),
+        });
+        offset += 4 + length;
+        if last {
+            return Ok((blocks, offset));
+        }
+    }
+}
+
+pub fn read(path: &Path) -> anyhow::Result<Comments> {
+    let (blocks, _) = blocks(&std::fs::read(path)?)?;
+    match blocks.iter().find(|block| block.kind == VORBIS_COMMENT) {
+        Some(block) => Comments::parse(&block.data),
+        None => Ok(Comments::default()),
+    }
+}
+
+/// swap the Vorbis comment block for `comments`, rewriting the whole file
+pub fn write(path: &Path, comments: &Comments) -> anyhow::Result<()> {
+    let file = std::fs::read(path)?;
+    let (mut blocks, audio) = blocks(&file)?;
+    let data = comments.to_bytes();
+    match blocks.iter_mut().find(|block| block.kind == VORBIS_COMMENT) {
+        Some(block) => block.data = data,
+        // right after the stream info, which has to come first
+        None => blocks.insert(1.min(blocks.len()), Block { kind: VORBIS_COMMENT, data }),
+    }
+
+    let mut output = Vec::with_capacity(file.len());
+    output.extend_from_slice(b"fLaC");
+    for (i, block) in blocks.iter().enumerate() {
+        let last = if i + 1 == blocks.len() { 0x80 } else { 0 };
+        anyhow::ensure!(block.data.len() < 1 << 24, "FLAC metadata block is too large");
+        output.push(last | block.kind);
+        output.extend_from_slice(&(block.data.len() as u32).to_be_bytes()[1..]);
+        output.extend_from_slice(&block.data);
+    }
+    output.extend_from_slice(&file[audio..]);
+    super::replace(path, &output)
+}
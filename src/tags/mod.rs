@@ -0,0 +1,216 @@
+//! writing what we learned about a track back into its tags, so the next scan starts out knowing it
+
+use std::fmt;
+use std::path::Path;
+
+use crate::analysis::percentile;
+use crate::audio::Audio;
+use crate::profile::{RideProfile, TempoSource};
+
+mod flac;
+mod ogg;
+mod vorbis;
+
+use vorbis::Comments;
+
+pub const BPM: &str = "BPM";
+pub const DIFFICULTY: &str = "MUSIC_RIDER_DIFFICULTY";
+pub const AVERAGE_LEVEL: &str = "MUSIC_RIDER_AVERAGE_LEVEL";
+
+/// what's worth keeping about a track
+#[derive(Debug, Clone)]
+pub struct Summary {
+    /// the tempo, if it came from somewhere better than the tags
+    pub bpm: Option<f64>,
+    /// from 1 (a stroll) to 10 (a climb), by the average and the hard parts of the level curve
+    pub difficulty: f64,
+    /// the average of the level curve, from 0.0 to 1.0
+    pub average_level: f64,
+}
+
+impl Summary {
+    pub fn of(profile: &RideProfile) -> Self {
+        let bpm = profile
+            .tempo
+            .as_ref()
+            .filter(|tempo| tempo.source != TempoSource::Tag)
+            .map(|tempo| tempo.bpm);
+        let average_level = if profile.levels.is_empty() {
+            0.
+        } else {
+            profile.levels.iter().sum::<f64>() / profile.levels.len() as f64
+        };
+        let hard = percentile(&profile.levels, 0.9);
+        Summary {
+            bpm,
+            difficulty: (1. + 9. * (average_level + hard) / 2.).round(),
+            average_level,
+        }
+    }
+
+    fn tags(&self) -> Vec<(&'static str, String)> {
+        let mut tags = Vec::new();
+        if let Some(bpm) = self.bpm {
+            tags.push((BPM, format_number(bpm)));
+        }
+        tags.push((DIFFICULTY, format!("{:.0}", self.difficulty)));
+        tags.push((AVERAGE_LEVEL, format!("{:.2}", self.average_level)));
+        tags
+    }
+}
+
+/// up to two decimals, without trailing zeros
+fn format_number(value: f64) -> String {
+    let text = format!("{value:.2}");
+    text.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+/// a tag that would change
+#[derive(Debug, Clone)]
+pub struct Change {
+    pub key: &'static str,
+    pub old: Option<String>,
+    pub new: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.key,
+            self.old.as_deref().unwrap_or("(none)"),
+            self.new
+        )
+    }
+}
+
+enum Format {
+    Flac,
+    Ogg,
+}
+
+fn format(path: &Path) -> anyhow::Result<Format> {
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "flac" => Ok(Format::Flac),
+        "ogg" | "oga" => Ok(Format::Ogg),
+        _ => anyhow::bail!("can't write tags to {}", path.display()),
+    }
+}
+
+fn read(path: &Path) -> anyhow::Result<Comments> {
+    match format(path)? {
+        Format::Flac => flac::read(path),
+        Format::Ogg => ogg::read(path),
+    }
+}
+
+/// the tags of `path` that differ from `summary`
+pub fn changes(path: &Path, summary: &Summary) -> anyhow::Result<Vec<Change>> {
+    let comments = read(path)?;
+    Ok(summary
+        .tags()
+        .into_iter()
+        .filter_map(|(key, new)| {
+            let old = comments.get(key).map(str::to_string);
+            (old.as_deref() != Some(new.as_str())).then_some(Change { key, old, new })
+        })
+        .collect())
+}
+
+pub fn write(path: &Path, changes: &[Change]) -> anyhow::Result<()> {
+    let mut comments = read(path)?;
+    for change in changes {
+        comments.set(change.key, &change.new);
+    }
+    match format(path)? {
+        Format::Flac => flac::write(path, &comments),
+        Format::Ogg => ogg::write(path, &comments),
+    }
+}
+
+/// write `bytes` to a file next to `path`, and move it over `path` once it's complete
+fn replace(path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tagging");
+    std::fs::write(&temporary, bytes)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// show how the tags of every track would change, and change them if `apply` is set
+pub fn run(audio: &Audio, apply: bool) -> anyhow::Result<()> {
+    let mut pending = 0;
+    for (index, track) in audio.tracks().iter().enumerate() {
+        if let Err(e) = format(track) {
+            println!("{e}, skipping it");
+            continue;
+        }
+        let summary = Summary::of(&audio.ride_profile(index)?);
+        let changes = changes(track, &summary)?;
+        println!("{}", track.display());
+        if changes.is_empty() {
+            println!("    up to date");
+            continue;
+        }
+        for change in &changes {
+            println!("    {change}");
+        }
+        if apply {
+            write(track, &changes)?;
+            println!("    written");
+        } else {
+            pending += 1;
+        }
+    }
+    if pending > 0 {
+        println!("dry run, nothing was written. run again with --write to update {pending} track(s)");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flac_roundtrip() -> anyhow::Result<()> {
+        // a stream info block, a comment block and two bytes standing in for the audio
+        let mut comments = Comments {
+            vendor: String::from("test"),
+            fields: vec![(String::from("TITLE"), String::from("song")), (String::from("bpm"), String::from("90"))],
+        };
+        let block = comments.to_bytes();
+        let mut file = b"fLaC".to_vec();
+        file.extend_from_slice(&[0, 0, 0, 34]);
+        file.extend_from_slice(&[0; 34]);
+        file.push(0x80 | 4);
+        file.extend_from_slice(&(block.len() as u32).to_be_bytes()[1..]);
+        file.extend_from_slice(&block);
+        file.extend_from_slice(&[0xff, 0xf8]);
+        let path = std::env::temp_dir().join(format!("music-rider-tags-{}.flac", std::process::id()));
+        std::fs::write(&path, &file)?;
+
+        let summary = Summary {
+            bpm: Some(127.5),
+            difficulty: 6.,
+            average_level: 0.4,
+        };
+        let changes = changes(&path, &summary)?;
+        assert_eq!(changes[0].to_string(), "BPM: 90 -> 127.5");
+        write(&path, &changes)?;
+        assert!(super::changes(&path, &summary)?.is_empty());
+
+        comments = read(&path)?;
+        assert_eq!(comments.get("title"), Some("song"));
+        assert_eq!(comments.get(DIFFICULTY), Some("6"));
+        assert_eq!(comments.fields.iter().filter(|(key, _)| key.eq_ignore_ascii_case(BPM)).count(), 1);
+        assert!(std::fs::read(&path)?.ends_with(&[0xff, 0xf8]));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
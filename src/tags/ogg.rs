@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::Path;
+
+use anyhow::Context as _;
+use ogg::{PacketReader, PacketWriteEndInfo, PacketWriter};
+
+use super::vorbis::Comments;
+
+/// the comment header of Ogg Vorbis and of Opus, which both wrap a Vorbis comment block
+const VORBIS_MAGIC: &[u8] = b"\x03vorbis";
+const OPUS_MAGIC: &[u8] = b"OpusTags";
+
+/// the comment block within a comment header packet
+fn comments(packet: &[u8]) -> Option<anyhow::Result<Comments>> {
+    packet
+        .strip_prefix(VORBIS_MAGIC)
+        .or_else(|| packet.strip_prefix(OPUS_MAGIC))
+        .map(Comments::parse)
+}
+
+pub fn read(path: &Path) -> anyhow::Result<Comments> {
+    let mut reader = PacketReader::new(BufReader::new(File::open(path)?));
+    // the comments are the second packet of the stream
+    for _ in 0..2 {
+        let Some(packet) = reader.read_packet()? else {
+            break;
+        };
+        if let Some(comments) = comments(&packet.data) {
+            return comments;
+        }
+    }
+    Ok(Comments::default())
+}
+
+/// swap the comment header for `comments`, rewriting every page of the file
+pub fn write(path: &Path, new_comments: &Comments) -> anyhow::Result<()> {
+    let mut reader = PacketReader::new(BufReader::new(File::open(path)?));
+    let mut writer = PacketWriter::new(Cursor::new(Vec::new()));
+    let mut replaced = false;
+    while let Some(packet) = reader.read_packet()? {
+        let mut data = packet.data.clone();
+        if !replaced && comments(&data).is_some() {
+            data = if data.starts_with(VORBIS_MAGIC) {
+                // vorbis ends the header with a framing bit
+                [VORBIS_MAGIC, &new_comments.to_bytes(), &[1]].concat()
+            } else {
+                [OPUS_MAGIC, new_comments.to_bytes().as_slice()].concat()
+            };
+            replaced = true;
+        }
+        let end = if packet.last_in_stream() {
+            PacketWriteEndInfo::EndStream
+        } else if packet.last_in_page() {
+            PacketWriteEndInfo::EndPage
+        } else {
+            PacketWriteEndInfo::NormalPacket
+        };
+        writer.write_packet(data.into_boxed_slice(), packet.stream_serial(), end, packet.absgp_page())?;
+    }
+    anyhow::ensure!(replaced, "no vorbis or opus comment header found");
+    super::replace(path, &writer.into_inner().into_inner()).context("failed to write ogg file")
+}
@@ -0,0 +1,84 @@
+use anyhow::Context as _;
+
+/// a Vorbis comment block, as used by FLAC, Ogg Vorbis and Opus
+#[derive(Debug, Clone, Default)]
+pub struct Comments {
+    pub vendor: String,
+    /// `KEY=value` pairs in file order, keys are case insensitive
+    pub fields: Vec<(String, String)>,
+}
+
+impl Comments {
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader { data };
+        let vendor = reader.string()?;
+        let count = reader.length()?;
+        let mut fields = Vec::with_capacity(count.min(1024));
+        for _ in 0..count {
+            let field = reader.string()?;
+            let (key, value) = field
+                .split_once('=')
+                .with_context(|| format!("vorbis comment `{field}` has no `=`"))?;
+            fields.push((key.to_string(), value.to_string()));
+        }
+        Ok(Comments { vendor, fields })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(self.vendor.as_bytes());
+        bytes.extend_from_slice(&(self.fields.len() as u32).to_le_bytes());
+        for (key, value) in &self.fields {
+            let field = format!("{key}={value}");
+            bytes.extend_from_slice(&(field.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(field.as_bytes());
+        }
+        bytes
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// replace every field named `key` with a single one
+    pub fn set(&mut self, key: &str, value: &str) {
+        match self.fields.iter().position(|(field, _)| field.eq_ignore_ascii_case(key)) {
+            Some(index) => {
+                self.fields[index] = (key.to_string(), value.to_string());
+                let mut i = 0;
+                self.fields.retain(|(field, _)| {
+                    i += 1;
+                    i - 1 == index || !field.eq_ignore_ascii_case(key)
+                });
+            }
+            None => self.fields.push((key.to_string(), value.to_string())),
+        }
+    }
+}
+
+/// length prefixed strings, one after the other
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, length: usize) -> anyhow::Result<&[u8]> {
+        anyhow::ensure!(self.data.len() >= length, "vorbis comments are cut short");
+        let (taken, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(taken)
+    }
+
+    fn length(&mut self) -> anyhow::Result<usize> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?) as usize)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        let length = self.length()?;
+        Ok(String::from_utf8_lossy(self.take(length)?).to_string())
+    }
+}
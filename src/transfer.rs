@@ -0,0 +1,200 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::Context as _;
+
+/// the shape of the map from a level curve value (0.0 to 1.0) to the share of the level range
+#[derive(Debug, Clone, PartialEq)]
+pub enum Curve {
+    Linear,
+    /// `value ^ gamma`: above 1 keeps most of the ride easy and saves the top for peaks, below 1 does the opposite
+    Gamma(f64),
+    /// exponential growth, steeper with a higher rate (negative rates bend the other way)
+    Exponential(f64),
+    /// straight lines between `(value, share)` points, sorted by value
+    Piecewise(Vec<(f64, f64)>),
+    /// an s-curve around `center`, pushing values apart the higher the contrast
+    Sigmoid { contrast: f64, center: f64 },
+}
+
+impl Curve {
+    /// map `value` (0.0 to 1.0) onto 0.0 to 1.0
+    pub fn apply(&self, value: f64) -> f64 {
+        let value = value.clamp(0., 1.);
+        let mapped = match self {
+            Curve::Linear => value,
+            Curve::Gamma(gamma) => value.powf(*gamma),
+            Curve::Exponential(rate) if rate.abs() < 1e-6 => value,
+            Curve::Exponential(rate) => (rate * value).exp_m1() / rate.exp_m1(),
+            Curve::Piecewise(points) => piecewise(points, value),
+            Curve::Sigmoid { contrast, center } => {
+                let sigmoid = |x: f64| 1. / (1. + (-contrast * (x - center)).exp());
+                let (low, high) = (sigmoid(0.), sigmoid(1.));
+                (sigmoid(value) - low) / (high - low)
+            }
+        };
+        mapped.clamp(0., 1.)
+    }
+}
+
+fn piecewise(points: &[(f64, f64)], value: f64) -> f64 {
+    let Some(&(first_x, first_y)) = points.first() else {
+        return value;
+    };
+    if value <= first_x {
+        return first_y;
+    }
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+        if value <= x1 {
+            return if x1 > x0 {
+                y0 + (y1 - y0) * (value - x0) / (x1 - x0)
+            } else {
+                y1
+            };
+        }
+    }
+    points.last().map_or(value, |&(_, y)| y)
+}
+
+impl FromStr for Curve {
+    type Err = anyhow::Error;
+
+    /// curves look like `gamma:2`, `exp:3`, `sigmoid:8:0.5` or `piecewise:0=0,0.5=0.2,1=1`
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (name, args) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        let number = |arg: &str| {
+            arg.trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|number| number.is_finite())
+                .with_context(|| format!("invalid number `{arg}` in curve `{s}`"))
+        };
+        let curve = match name {
+            "linear" => Curve::Linear,
+            "gamma" => Curve::Gamma(number(args)?),
+            "exp" => Curve::Exponential(number(args)?),
+            "sigmoid" => {
+                let (contrast, center) = args.split_once(':').unwrap_or((args, "0.5"));
+                Curve::Sigmoid {
+                    contrast: number(contrast)?,
+                    center: number(center)?,
+                }
+            }
+            "piecewise" => {
+                let mut points = args
+                    .split(',')
+                    .map(|point| {
+                        let (x, y) = point
+                            .split_once('=')
+                            .with_context(|| format!("expected value=share in curve `{s}`, got `{point}`"))?;
+                        Ok((number(x)?, number(y)?))
+                    })
+                    .collect::<anyhow::Result<Vec<_>>>()?;
+                points.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Curve::Piecewise(points)
+            }
+            _ => anyhow::bail!(
+                "unknown curve `{s}`, expected one of: linear, gamma:GAMMA, exp:RATE, sigmoid:CONTRAST[:CENTER], piecewise:VALUE=SHARE,..."
+            ),
+        };
+        if let Curve::Gamma(gamma) = curve
+            && gamma <= 0.
+        {
+            anyhow::bail!("gamma has to be above 0, got {gamma}");
+        }
+        // a flat sigmoid maps everything to 0/0
+        if let Curve::Sigmoid { contrast, .. } = curve
+            && contrast == 0.
+        {
+            anyhow::bail!("the sigmoid contrast can't be 0");
+        }
+        Ok(curve)
+    }
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Curve::Linear => write!(f, "linear"),
+            Curve::Gamma(gamma) => write!(f, "gamma:{gamma}"),
+            Curve::Exponential(rate) => write!(f, "exp:{rate}"),
+            Curve::Piecewise(points) => {
+                let points: Vec<String> = points.iter().map(|(x, y)| format!("{x}={y}")).collect();
+                write!(f, "piecewise:{}", points.join(","))
+            }
+            Curve::Sigmoid { contrast, center } => write!(f, "sigmoid:{contrast}:{center}"),
+        }
+    }
+}
+
+/// turns level curve values (0.0 to 1.0) into equipment levels (`min` to `max`)
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub curve: Curve,
+    /// the lowest level the equipment is ever set to
+    pub min: i16,
+    pub max: i16,
+}
+
+impl Transfer {
+    pub fn level(&self, value: f64) -> i16 {
+        let (min, max) = (self.min as f64, self.max as f64);
+        (self.curve.apply(value) * (max - min) + min) as i16
+    }
+
+    /// a bar per step of the level curve, to get a feel for the curve before riding it
+    pub fn preview(&self) -> String {
+        let mut preview = format!("curve {} :: levels {} to {}\n", self.curve, self.min, self.max);
+        for step in 0..=20 {
+            let value = step as f64 / 20.;
+            let level = self.level(value);
+            preview.push_str(&format!(
+                "{value:.2} :: level {level:>3} {}\n",
+                "#".repeat(level.max(0) as usize / 2)
+            ));
+        }
+        preview
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_curves() -> anyhow::Result<()> {
+        let linear = Transfer {
+            curve: Curve::Linear,
+            min: 1,
+            max: 50,
+        };
+        assert_eq!(linear.level(0.), 1);
+        assert_eq!(linear.level(0.5), 25);
+        assert_eq!(linear.level(1.), 50);
+
+        for curve in ["gamma:2", "exp:3", "sigmoid:8:0.5", "piecewise:0=0,0.5=0.2,1=1"] {
+            let curve: Curve = curve.parse()?;
+            assert_eq!(curve.apply(0.), 0., "{curve}");
+            assert!((curve.apply(1.) - 1.).abs() < 1e-9, "{curve}");
+            // all of these keep the lower half easier than a straight line would
+            assert!(curve.apply(0.25) < 0.25, "{curve}");
+            assert_eq!(curve.to_string().parse::<Curve>()?, curve);
+        }
+        assert!((Curve::Piecewise(vec![(0., 0.), (0.5, 0.2), (1., 1.)]).apply(0.75) - 0.6).abs() < 1e-9);
+        assert!("wobbly".parse::<Curve>().is_err());
+        assert!("gamma:0".parse::<Curve>().is_err());
+        assert!("sigmoid:0".parse::<Curve>().is_err());
+        assert!("sigmoid:inf:0.5".parse::<Curve>().is_err());
+        assert!("sigmoid:8:NaN".parse::<Curve>().is_err());
+
+        let floored = Transfer {
+            curve: Curve::Gamma(2.),
+            min: 10,
+            max: 50,
+        };
+        assert_eq!(floored.level(0.), 10);
+        assert_eq!(floored.level(1.), 50);
+        Ok(())
+    }
+}
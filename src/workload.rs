@@ -0,0 +1,243 @@
+//! fitting a ride to how hard it should be ("450 kJ today") instead of to a scale value
+//!
+//! the whole album is planned up front, the watts of every second predicted, and the level curve
+//! bent until the total matches
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::planner::{Limits, Plan};
+use crate::profile::RideProfile;
+use crate::transfer::{Curve, Transfer};
+
+/// seconds of the rolling average normalized power is computed over
+const ROLLING: f64 = 30.;
+/// the range of gammas searched, from everything near the top to everything near the bottom
+const GAMMAS: (f64, f64) = (0.1, 10.);
+/// steps of the bisection, plenty to land within a fraction of a kJ
+const STEPS: usize = 40;
+/// the cadence predictions assume, for equipment that takes levels
+pub const CADENCE: f64 = 85.;
+
+/// how hard the ride should be
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    Kilojoules(f64),
+    /// training stress score, needs the FTP
+    Tss(f64),
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    /// "450kJ" or "80tss"
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let lower = s.trim().to_lowercase();
+        let (number, target): (&str, fn(f64) -> Target) = if let Some(number) = lower.strip_suffix("kj") {
+            (number, Target::Kilojoules)
+        } else if let Some(number) = lower.strip_suffix("tss") {
+            (number, Target::Tss)
+        } else {
+            anyhow::bail!("expected a workload like 450kJ or 80tss, got `{s}`");
+        };
+        let value: f64 = number.trim().parse()?;
+        if value <= 0. {
+            anyhow::bail!("the workload has to be more than nothing, got `{s}`");
+        }
+        Ok(target(value))
+    }
+}
+
+impl Target {
+    /// the number of kJ or TSS
+    pub fn value(&self) -> f64 {
+        match self {
+            Target::Kilojoules(value) | Target::Tss(value) => *value,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Kilojoules(kj) => write!(f, "{kj:.0} kJ"),
+            Target::Tss(tss) => write!(f, "{tss:.0} TSS"),
+        }
+    }
+}
+
+/// work and stress of a ride, accumulated a reading at a time
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    seconds: f64,
+    joules: f64,
+    /// the readings of the last `ROLLING` seconds, with how long each one lasted
+    window: VecDeque<(f64, f64)>,
+    window_seconds: f64,
+    window_joules: f64,
+    /// the fourth power of the rolling average, integrated over time
+    rolling_fourth: f64,
+}
+
+impl Progress {
+    /// `watts` held for `seconds`
+    pub fn push(&mut self, watts: f64, seconds: f64) {
+        if seconds <= 0. {
+            return;
+        }
+        self.seconds += seconds;
+        self.joules += watts * seconds;
+        self.window.push_back((seconds, watts));
+        self.window_seconds += seconds;
+        self.window_joules += watts * seconds;
+        // the latest reading always stays, however long it lasted
+        while self.window_seconds > ROLLING
+            && self.window.len() > 1
+            && let Some((oldest, oldest_watts)) = self.window.pop_front()
+        {
+            self.window_seconds -= oldest;
+            self.window_joules -= oldest * oldest_watts;
+        }
+        let rolling = self.window_joules / self.window_seconds;
+        self.rolling_fourth += rolling.powi(4) * seconds;
+    }
+
+    pub fn kilojoules(&self) -> f64 {
+        self.joules / 1000.
+    }
+
+    pub fn normalized_power(&self) -> f64 {
+        if self.seconds > 0. {
+            (self.rolling_fourth / self.seconds).powf(0.25)
+        } else {
+            0.
+        }
+    }
+
+    /// training stress score, 100 being an hour at the FTP
+    pub fn tss(&self, ftp: f64) -> f64 {
+        let intensity = self.normalized_power() / ftp;
+        self.seconds * self.normalized_power() * intensity / (ftp * 3600.) * 100.
+    }
+
+    /// "450 kJ :: NP 180 W :: 72 TSS over 65 min", the TSS if the FTP is known
+    pub fn report(&self, ftp: Option<f64>) -> String {
+        let tss = ftp.map(|ftp| format!(" :: {:.0} TSS", self.tss(ftp))).unwrap_or_default();
+        format!(
+            "{:.0} kJ :: NP {:.0} W{tss} over {:.0} min",
+            self.kilojoules(),
+            self.normalized_power(),
+            self.seconds / 60.
+        )
+    }
+
+    /// how far along the target is, in its own unit
+    pub fn of(&self, target: Target, ftp: Option<f64>) -> f64 {
+        match (target, ftp) {
+            (Target::Kilojoules(_), _) => self.kilojoules(),
+            (Target::Tss(_), Some(ftp)) => self.tss(ftp),
+            (Target::Tss(_), None) => 0.,
+        }
+    }
+}
+
+/// the predicted workload of riding every profile with `transfer`, given what a level is worth in watts
+pub fn predict(
+    profiles: &[RideProfile],
+    transfer: &Transfer,
+    limits: &Limits,
+    watts: &dyn Fn(i16) -> Option<f64>,
+) -> Progress {
+    let mut progress = Progress::default();
+    for profile in profiles {
+        let plan = Plan::new(profile, transfer, limits);
+        let mut time = 0.;
+        while time < profile.track.duration {
+            if let Some(watts) = plan.level_at(time).and_then(watts) {
+                progress.push(watts, 1.);
+            }
+            time += 1.;
+        }
+    }
+    progress
+}
+
+/// the gamma curve whose predicted workload comes closest to `target`, and that prediction
+pub fn fit(
+    profiles: &[RideProfile],
+    transfer: &Transfer,
+    limits: &Limits,
+    watts: &dyn Fn(i16) -> Option<f64>,
+    target: Target,
+    ftp: Option<f64>,
+) -> (Curve, Progress) {
+    let goal = target.value();
+    let predict_with = |gamma: f64| {
+        let transfer = Transfer {
+            curve: Curve::Gamma(gamma),
+            ..transfer.clone()
+        };
+        predict(profiles, &transfer, limits, watts)
+    };
+    // a higher gamma keeps the ride lower, so the work only goes down as the gamma goes up
+    let (mut low, mut high) = (GAMMAS.0.ln(), GAMMAS.1.ln());
+    for _ in 0..STEPS {
+        let middle = (low + high) / 2.;
+        if predict_with(middle.exp()).of(target, ftp) > goal {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    // rounded, so the curve reads well when it's printed
+    let gamma = (((low + high) / 2.).exp() * 100.).round() / 100.;
+    (Curve::Gamma(gamma), predict_with(gamma))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::TrackInfo;
+
+    #[test]
+    fn test_fit_to_kilojoules() {
+        let mut profile = RideProfile::new(
+            TrackInfo {
+                path: "song.flac".into(),
+                sample_rate: 44_100,
+                channels: 2,
+                duration: 600.,
+                metadata: Default::default(),
+            },
+            None,
+            1.,
+        );
+        profile.levels = (0..600).map(|second| (second % 120) as f64 / 120.).collect();
+        let transfer = Transfer {
+            curve: Curve::Linear,
+            min: 1,
+            max: 21,
+        };
+        let limits = Limits {
+            ramp_rate: None,
+            write_interval: 1.,
+            max_writes: None,
+        };
+        // 100 to 300 W over the levels
+        let watts = |level: i16| Some(100. + (level - 1) as f64 * 10.);
+
+        let linear = predict(std::slice::from_ref(&profile), &transfer, &limits, &watts);
+        assert!((linear.kilojoules() - 120.).abs() < 5., "{}", linear.kilojoules());
+
+        let (curve, fitted) = fit(&[profile], &transfer, &limits, &watts, "90kJ".parse().unwrap(), None);
+        assert!(matches!(curve, Curve::Gamma(gamma) if gamma > 1.), "{curve:?}");
+        assert!((fitted.kilojoules() - 90.).abs() < 1., "{}", fitted.kilojoules());
+
+        // an hour at the FTP is 100
+        let mut hour = Progress::default();
+        hour.push(250., 3600.);
+        assert!((hour.tss(250.) - 100.).abs() < 1e-6);
+        assert_eq!("80 TSS".replace(' ', "").parse::<Target>().unwrap(), Target::Tss(80.));
+    }
+}
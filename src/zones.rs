@@ -0,0 +1,217 @@
+//! training zones, so a ride asks the same of everyone relative to their FTP
+//!
+//! zones follow Coggan's power levels, in percent of the functional threshold power
+
+use std::fmt;
+
+use crate::profile::{FeatureCurve, RideProfile, SectionLabel};
+
+/// how long a drop gets to ask for anaerobic efforts before it eases into VO2 max
+const DROP_BURST: f64 = 15.;
+/// levels below this are quiet parts, whatever section they're in
+const QUIET: f64 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    Recovery,
+    Endurance,
+    Tempo,
+    Threshold,
+    Vo2Max,
+    Anaerobic,
+    Neuromuscular,
+}
+
+impl Zone {
+    pub const ALL: [Zone; 7] = [
+        Zone::Recovery,
+        Zone::Endurance,
+        Zone::Tempo,
+        Zone::Threshold,
+        Zone::Vo2Max,
+        Zone::Anaerobic,
+        Zone::Neuromuscular,
+    ];
+
+    /// where the zone starts, in percent of the FTP
+    fn floor(self) -> f64 {
+        match self {
+            Zone::Recovery => 0.,
+            Zone::Endurance => 55.,
+            Zone::Tempo => 75.,
+            Zone::Threshold => 90.,
+            Zone::Vo2Max => 105.,
+            Zone::Anaerobic => 120.,
+            Zone::Neuromuscular => 150.,
+        }
+    }
+
+    /// the zone `watts` fall into
+    pub fn of(watts: f64, ftp: f64) -> Zone {
+        let percent = watts / ftp * 100.;
+        Zone::ALL
+            .into_iter()
+            .rev()
+            .find(|zone| percent >= zone.floor())
+            .unwrap_or(Zone::Recovery)
+    }
+
+    /// the watts to ride the zone at, the middle of it
+    pub fn target(self, ftp: f64) -> f64 {
+        let ceiling = match self {
+            Zone::Neuromuscular => 170.,
+            zone => Zone::ALL[zone.number()].floor(),
+        };
+        (self.floor() + ceiling) / 2. / 100. * ftp
+    }
+
+    /// Z1 to Z7
+    pub fn number(self) -> usize {
+        self as usize + 1
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Zone::Recovery => "recovery",
+            Zone::Endurance => "endurance",
+            Zone::Tempo => "tempo",
+            Zone::Threshold => "threshold",
+            Zone::Vo2Max => "vo2 max",
+            Zone::Anaerobic => "anaerobic",
+            Zone::Neuromuscular => "neuromuscular",
+        };
+        write!(f, "Z{} {name}", self.number())
+    }
+}
+
+/// the zone the music asks for at `time` seconds into the track
+///
+/// quiet parts are endurance, choruses threshold and drops a short anaerobic burst that eases
+/// into vo2 max. everything else goes by the level
+fn zone_at(profile: &RideProfile, time: f64, level: f64) -> Zone {
+    match profile.section_at(time) {
+        Some(section) if section.label == SectionLabel::Drop => {
+            if time - section.start < DROP_BURST {
+                Zone::Anaerobic
+            } else {
+                Zone::Vo2Max
+            }
+        }
+        _ if level < QUIET => Zone::Endurance,
+        Some(section) if section.label == SectionLabel::Chorus => Zone::Threshold,
+        Some(section) if matches!(section.label, SectionLabel::Intro | SectionLabel::Outro) => Zone::Endurance,
+        _ if level < 0.55 => Zone::Tempo,
+        _ if level < 0.85 => Zone::Threshold,
+        _ => Zone::Vo2Max,
+    }
+}
+
+/// add the `zone` (1 to 7) and `target_watts` features for a rider with the given FTP
+pub fn apply(profile: &mut RideProfile, ftp: f64) {
+    let zones: Vec<Zone> = profile
+        .levels
+        .iter()
+        .enumerate()
+        .map(|(frame, &level)| zone_at(profile, frame as f64 / profile.frame_rate, level))
+        .collect();
+    profile
+        .features
+        .retain(|curve| curve.name != "zone" && curve.name != "target_watts");
+    profile.features.push(FeatureCurve {
+        name: String::from("zone"),
+        values: zones.iter().map(|zone| zone.number() as f64).collect(),
+    });
+    profile.features.push(FeatureCurve {
+        name: String::from("target_watts"),
+        values: zones.iter().map(|zone| zone.target(ftp)).collect(),
+    });
+}
+
+/// the watts the zones ask for at `position`, if they were applied
+pub fn target_watts(profile: &RideProfile, position: f64) -> Option<f64> {
+    let curve = profile.feature("target_watts")?;
+    curve.values.get(profile.frame_at(position)).copied()
+}
+
+/// how long the rider spent in every zone, by the power they actually put out
+#[derive(Debug, Clone, Default)]
+pub struct TimeInZone {
+    seconds: [f64; 7],
+}
+
+impl TimeInZone {
+    pub fn add(&mut self, watts: f64, ftp: f64, seconds: f64) {
+        self.seconds[Zone::of(watts, ftp) as usize] += seconds;
+    }
+
+    pub fn total(&self) -> f64 {
+        self.seconds.iter().sum()
+    }
+}
+
+impl fmt::Display for TimeInZone {
+    /// a line per zone, with the time and a bar of its share of the ride
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().max(f64::EPSILON);
+        for (zone, &seconds) in Zone::ALL.iter().zip(&self.seconds) {
+            let share = seconds / total;
+            writeln!(
+                f,
+                "{:<18} {:>3}:{:02} {:>4.0}% {}",
+                zone.to_string(),
+                (seconds / 60.) as u64,
+                (seconds % 60.) as u64,
+                share * 100.,
+                "#".repeat((share * 40.).round() as usize)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{Section, TrackInfo};
+
+    #[test]
+    fn test_zones_follow_the_song() {
+        let mut profile = RideProfile::new(
+            TrackInfo {
+                path: "song.flac".into(),
+                sample_rate: 44_100,
+                channels: 2,
+                duration: 90.,
+                metadata: Default::default(),
+            },
+            None,
+            1.,
+        );
+        profile.levels = (0..90).map(|second| if second < 30 { 0.1 } else { 0.9 }).collect();
+        let section = |start: f64, end: f64, label| Section {
+            start,
+            end,
+            label,
+            level: 0.,
+        };
+        profile.sections = vec![
+            section(0., 30., SectionLabel::Intro),
+            section(30., 60., SectionLabel::Chorus),
+            section(60., 90., SectionLabel::Drop),
+        ];
+
+        apply(&mut profile, 200.);
+        let zone = |second: usize| profile.feature("zone").unwrap().values[second];
+        assert_eq!([zone(10), zone(40), zone(65), zone(80)], [2., 4., 6., 5.]);
+        assert_eq!(target_watts(&profile, 10.), Some(130.));
+        assert_eq!(Zone::of(target_watts(&profile, 65.).unwrap(), 200.), Zone::Anaerobic);
+
+        let mut time = TimeInZone::default();
+        time.add(130., 200., 60.);
+        time.add(300., 200., 30.);
+        assert_eq!(time.total(), 90.);
+        assert!(time.to_string().contains("Z2 endurance         1:00   67%"), "{time}");
+    }
+}